@@ -0,0 +1,250 @@
+use crate::ratelimit::RateLimiter;
+use eyre::{eyre, Result};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, COOKIE, REFERER};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const TOKEN_URL: &str = "https://osu.ppy.sh/oauth/token";
+// Refresh a little before the token actually expires
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+pub const CREDENTIALS_PATH: &str = "credentials.yaml";
+const KEYRING_SERVICE: &str = "osu-beatmap-downloader";
+
+// How a mirror authenticates downloads. The OAuth client's token is only for API reads,
+// the website refuses it for downloads.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthKind {
+    // `osu_session` cookie imported from a logged-in browser
+    Session,
+}
+
+// Secrets kept out of `config.yaml`, written owner-readable only.
+// The session cookie goes to the OS keyring instead, files are readable by anyone on Windows.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct Credentials {
+    // Only read from files written before the keyring was used
+    #[serde(default, skip_serializing)]
+    pub session_cookie: Option<String>,
+    // Client credentials token for API reads, it can't download
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    #[serde(default)]
+    pub expires_at: i64,
+}
+
+impl Credentials {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut credentials: Self = if path.exists() {
+            serde_yaml::from_reader(std::fs::File::open(path)?)?
+        } else {
+            Self::default()
+        };
+        if credentials.session_cookie.is_some() {
+            // Move a plaintext cookie into the keyring
+            if let Err(e) = credentials.save(path) {
+                log::warn!("can't move the session cookie to the keyring: {}", e);
+            }
+            return Ok(credentials);
+        }
+        credentials.session_cookie = match keyring_entry(path)?.get_password() {
+            Ok(cookie) => Some(cookie),
+            Err(keyring::Error::NoEntry) => None,
+            Err(e) => {
+                log::warn!("can't read the session cookie from the keyring: {}", e);
+                None
+            }
+        };
+        Ok(credentials)
+    }
+
+    // Fails rather than writing the cookie to disk when there's no keyring
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let entry = keyring_entry(path)?;
+        match &self.session_cookie {
+            Some(cookie) => entry.set_password(cookie)?,
+            None => match entry.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => (),
+                Err(e) => return Err(e.into()),
+            },
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(serde_yaml::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn valid_token(&self) -> Option<&str> {
+        let now = chrono::Utc::now().timestamp();
+        match &self.access_token {
+            Some(token) if self.expires_at - EXPIRY_MARGIN_SECS > now => Some(token),
+            _ => None,
+        }
+    }
+}
+
+// Named after the credentials file, so each profile has its own cookie
+fn keyring_entry(path: &Path) -> Result<keyring::Entry> {
+    Ok(keyring::Entry::new(
+        KEYRING_SERVICE,
+        &path.display().to_string(),
+    )?)
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+// Authentication for downloads from the official osu! website.
+pub struct OsuAuth {
+    client_id: u64,
+    client_secret: String,
+    path: PathBuf,
+    client: reqwest::Client,
+    limiter: Arc<RateLimiter>,
+    credentials: Mutex<Credentials>,
+}
+
+impl OsuAuth {
+    // Unreadable credentials are logged and replaced, they shouldn't stop the app from starting
+    pub fn new(
+        client_id: u64,
+        client_secret: String,
+        path: PathBuf,
+        limiter: Arc<RateLimiter>,
+    ) -> Self {
+        let credentials = Credentials::load(&path).unwrap_or_else(|e| {
+            log::warn!("ignoring unreadable '{}': {}", path.display(), e);
+            Credentials::default()
+        });
+        Self {
+            client_id,
+            client_secret,
+            path,
+            client: reqwest::Client::new(),
+            limiter,
+            credentials: Mutex::new(credentials),
+        }
+    }
+
+    pub async fn has_session_cookie(&self) -> bool {
+        self.credentials.lock().await.session_cookie.is_some()
+    }
+
+    // Replace the stored cookie, an empty one removes it
+    pub async fn set_session_cookie(&self, cookie: &str) -> Result<()> {
+        let mut credentials = self.credentials.lock().await;
+        let cookie = cookie.trim();
+        credentials.session_cookie = (!cookie.is_empty()).then(|| cookie.to_owned());
+        credentials.save(&self.path)
+    }
+
+    // Bearer token of the OAuth client, for API reads
+    pub async fn api_headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        let token = self.access_token().await?;
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token))?,
+        );
+        Ok(headers)
+    }
+
+    pub async fn headers(&self, kind: AuthKind) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(REFERER, HeaderValue::from_static("https://osu.ppy.sh/"));
+        match kind {
+            AuthKind::Session => {
+                let credentials = self.credentials.lock().await;
+                let session = credentials
+                    .session_cookie
+                    .as_deref()
+                    .ok_or_else(|| eyre!("no osu! session cookie imported"))?;
+                headers.insert(
+                    COOKIE,
+                    HeaderValue::from_str(&format!("osu_session={}", session))?,
+                );
+            }
+        }
+        Ok(headers)
+    }
+
+    // Drop the cached token after the server rejected it.
+    pub async fn invalidate(&self) {
+        self.credentials.lock().await.access_token = None;
+    }
+
+    async fn access_token(&self) -> Result<String> {
+        let mut credentials = self.credentials.lock().await;
+        if let Some(token) = credentials.valid_token() {
+            return Ok(token.to_owned());
+        }
+
+        let client_id = self.client_id.to_string();
+        let form = [
+            ("client_id", client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("grant_type", "client_credentials"),
+            ("scope", "public"),
+        ];
+        let res = self
+            .limiter
+            .send(self.client.post(TOKEN_URL).form(&form))
+            .await?;
+        if !res.status().is_success() {
+            return Err(eyre!("Token request failed with status: {}", res.status()));
+        }
+        let token = res
+            .json::<TokenResponse>()
+            .await
+            .map_err(|e| eyre!("can't map to token response struct: {}", e))?;
+
+        credentials.access_token = Some(token.access_token.clone());
+        credentials.expires_at = chrono::Utc::now().timestamp() + token.expires_in;
+        if let Err(e) = credentials.save(&self.path) {
+            log::warn!("failed to store osu! credentials: {}", e);
+        }
+        Ok(token.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_roundtrip() {
+        // Mock entries don't share what's stored, the cookie only has to stay out of the file
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let path = std::env::temp_dir().join("downloader_test_credentials.yaml");
+        let credentials = Credentials {
+            session_cookie: Some("secret-cookie".to_owned()),
+            access_token: Some("token".to_owned()),
+            expires_at: chrono::Utc::now().timestamp() + 3600,
+        };
+        credentials.save(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let loaded = Credentials::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!text.contains("secret-cookie"));
+        assert_eq!(loaded.valid_token(), Some("token"));
+
+        let expired = Credentials {
+            expires_at: chrono::Utc::now().timestamp() + EXPIRY_MARGIN_SECS / 2,
+            ..loaded
+        };
+        assert_eq!(expired.valid_token(), None);
+    }
+}
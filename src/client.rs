@@ -1,19 +1,157 @@
+pub mod aggregate;
+pub mod beatconnect;
+pub mod catboy;
 pub mod nerinyan;
 pub mod osu;
-pub mod osu_api;
+pub mod osu_direct;
 
+use crate::utils::lenient_datetime;
 use async_trait::async_trait;
-use eyre::Result;
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Beatmapset {
     pub id: u32,
     pub title: String,
+    #[serde(default)]
+    pub artist: String,
+    #[serde(default)]
+    pub creator: String,
+    #[serde(default, deserialize_with = "lenient_datetime")]
+    pub ranked_date: Option<DateTime<Utc>>,
 }
+
+impl Beatmapset {
+    // Fill in metadata this result is missing from another result for the same set
+    pub fn merge(&mut self, other: Beatmapset) {
+        if self.title.is_empty() {
+            self.title = other.title;
+        }
+        if self.artist.is_empty() {
+            self.artist = other.artist;
+        }
+        if self.creator.is_empty() {
+            self.creator = other.creator;
+        }
+        if self.ranked_date.is_none() {
+            self.ranked_date = other.ranked_date;
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    #[default]
+    Osu,
+    Taiko,
+    Fruits,
+    Mania,
+}
+
+impl Mode {
+    // Ruleset number of the osu! API
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Osu => "osu",
+            Mode::Taiko => "taiko",
+            Mode::Fruits => "fruits",
+            Mode::Mania => "mania",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    #[default]
+    Ranked,
+    Loved,
+    Qualified,
+}
+
+impl Status {
+    // Rank status number of the osu! API
+    pub fn id(self) -> i8 {
+        match self {
+            Status::Ranked => 1,
+            Status::Qualified => 3,
+            Status::Loved => 4,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Ranked => "ranked",
+            Status::Loved => "loved",
+            Status::Qualified => "qualified",
+        }
+    }
+}
+
+// Which sets a search client lists
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Filter {
+    pub mode: Mode,
+    pub status: Status,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchPage {
+    pub songs: Vec<Beatmapset>,
+    // Cursor for the following page, `None` once there are no more results
+    pub next: Option<String>,
+    // Backends that failed while the others still returned results
+    pub failed: Vec<(String, String)>,
+}
+
 #[async_trait]
 pub trait SearchClient: Send + Sync {
-    async fn fetch_new_songs(&self, num: u32) -> Result<Vec<Beatmapset>>;
+    // Fetch one page of results, starting from the first page when `cursor` is `None`
+    async fn fetch_page(&self, cursor: Option<String>) -> Result<SearchPage>;
+
+    // Whether pages come newest ranked first, so a search since a date can stop early
+    fn sorted_by_ranked_date(&self) -> bool {
+        true
+    }
+
+    // Like `fetch_page`, but results are done once they're ranked before `since`.
+    // Pages are sorted by ranked date, so the first older song ends the search.
+    async fn fetch_page_since(
+        &self,
+        cursor: Option<String>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<SearchPage> {
+        let mut page = self.fetch_page(cursor).await?;
+        if let Some(since) = since {
+            if !page.songs.is_empty() && page.songs.iter().all(|s| s.ranked_date.is_none()) {
+                return Err(eyre!("search results have no ranked date"));
+            }
+            if self.sorted_by_ranked_date()
+                && page
+                    .songs
+                    .iter()
+                    .any(|s| s.ranked_date.is_some_and(|d| d < since))
+            {
+                page.next = None;
+            }
+        }
+        Ok(page)
+    }
+}
+
+// Cursor of clients that page by number
+fn page_number(cursor: Option<String>) -> Result<u32> {
+    match cursor {
+        Some(cursor) => Ok(cursor.parse()?),
+        None => Ok(0),
+    }
 }
 
 #[cfg(test)]
@@ -21,12 +159,128 @@ mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
+    async fn fetch_new_songs(client: &dyn SearchClient, num: u32) -> Result<Vec<Beatmapset>> {
+        let mut songs = Vec::new();
+        let mut cursor = None;
+        for _ in 0..num / 50 {
+            let page = client.fetch_page(cursor).await?;
+            songs.extend(page.songs);
+            if page.next.is_none() {
+                break;
+            }
+            cursor = page.next;
+        }
+        Ok(songs)
+    }
+
+    struct StaticClient(Option<Vec<(u32, &'static str)>>);
+
+    #[async_trait]
+    impl SearchClient for StaticClient {
+        async fn fetch_page(&self, _cursor: Option<String>) -> Result<SearchPage> {
+            match &self.0 {
+                Some(songs) => Ok(SearchPage {
+                    songs: songs
+                        .iter()
+                        .map(|(id, title)| Beatmapset {
+                            id: *id,
+                            title: title.to_string(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                }),
+                None => Err(eyre::eyre!("backend down")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_search() {
+        let client = aggregate::AggregateClient::new(vec![
+            (
+                "a".to_owned(),
+                Box::new(StaticClient(Some(vec![(1, ""), (2, "two")]))),
+            ),
+            ("b".to_owned(), Box::new(StaticClient(None))),
+            (
+                "c".to_owned(),
+                Box::new(StaticClient(Some(vec![(3, "three"), (1, "one")]))),
+            ),
+        ]);
+        let page = client.fetch_page(None).await.unwrap();
+        assert_eq!(page.failed.len(), 1);
+        assert_eq!(page.failed[0].0, "b");
+        assert_eq!(page.next, None);
+        let songs = page
+            .songs
+            .iter()
+            .map(|s| (s.id, s.title.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(songs, vec![(1, "one"), (2, "two"), (3, "three")]);
+    }
+
+    struct DatedClient;
+
+    #[async_trait]
+    impl SearchClient for DatedClient {
+        async fn fetch_page(&self, _cursor: Option<String>) -> Result<SearchPage> {
+            let songs = serde_json::from_str(
+                r#"[
+                    {"id": 3, "title": "three", "ranked_date": "2024-03-01T00:00:00Z"},
+                    {"id": 2, "title": "two", "ranked_date": "2024-02-01 00:00:00"},
+                    {"id": 1, "title": "one", "ranked_date": 1704067200}
+                ]"#,
+            )?;
+            Ok(SearchPage {
+                songs,
+                next: Some("1".to_owned()),
+                ..Default::default()
+            })
+        }
+    }
+
+    struct UnsortedClient;
+
+    #[async_trait]
+    impl SearchClient for UnsortedClient {
+        async fn fetch_page(&self, cursor: Option<String>) -> Result<SearchPage> {
+            DatedClient.fetch_page(cursor).await
+        }
+
+        fn sorted_by_ranked_date(&self) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_page_since() {
+        let date = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let page = DatedClient.fetch_page_since(None, None).await.unwrap();
+        assert_eq!(
+            page.songs[1].ranked_date,
+            Some(date("2024-02-01T00:00:00Z"))
+        );
+        assert_eq!(page.songs[2].ranked_date, None);
+        assert!(page.next.is_some());
+
+        let since = Some(date("2024-01-15T00:00:00Z"));
+        let page = DatedClient.fetch_page_since(None, since).await.unwrap();
+        assert!(page.next.is_some());
+
+        let since = Some(date("2024-02-15T00:00:00Z"));
+        let page = DatedClient.fetch_page_since(None, since).await.unwrap();
+        assert!(page.next.is_none());
+        // Older songs say nothing about the next page when results aren't in ranked order
+        let page = UnsortedClient.fetch_page_since(None, since).await.unwrap();
+        assert!(page.next.is_some());
+    }
+
     #[tokio::test]
     async fn test_nerinyan_search() {
-        let client = nerinyan::NerinyanClient::new();
+        let client = nerinyan::NerinyanClient::new(Default::default(), Default::default());
         assert_eq!(
-            client
-                .fetch_new_songs(100)
+            fetch_new_songs(&client, 100)
                 .await
                 .expect("failed to search song")
                 .len(),
@@ -36,10 +290,33 @@ mod tests {
 
     #[tokio::test]
     async fn test_osu_search() {
-        let client = osu::OsuClient::new();
+        let client = osu::OsuClient::new(Default::default(), Default::default());
         assert_eq!(
-            client
-                .fetch_new_songs(100)
+            fetch_new_songs(&client, 100)
+                .await
+                .expect("failed to search song")
+                .len(),
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn test_catboy_search() {
+        let client = catboy::CatboyClient::new(Default::default(), Default::default());
+        assert_eq!(
+            fetch_new_songs(&client, 100)
+                .await
+                .expect("failed to search song")
+                .len(),
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn test_osu_direct_search() {
+        let client = osu_direct::OsuDirectClient::new(Default::default(), Default::default());
+        assert_eq!(
+            fetch_new_songs(&client, 100)
                 .await
                 .expect("failed to search song")
                 .len(),
@@ -49,11 +326,18 @@ mod tests {
 
     #[tokio::test]
     async fn test_osu_api_search() -> Result<()> {
-        let config = crate::settings::read_config_from_yaml("config.yaml").unwrap();
-        let client = rosu_v2::Osu::new(config.client_id, config.client_secret).await?;
+        let config = crate::settings::read_config_from_yaml("config.yaml", None).unwrap();
+        let limiter = std::sync::Arc::new(crate::ratelimit::RateLimiter::default());
+        let path = std::env::temp_dir().join("osu_api_search_credentials.yaml");
+        let auth = crate::auth::OsuAuth::new(
+            config.client_id,
+            config.client_secret,
+            path,
+            limiter.clone(),
+        );
+        let client = osu::OsuClient::api(limiter, std::sync::Arc::new(auth), Default::default());
         assert_eq!(
-            client
-                .fetch_new_songs(100)
+            fetch_new_songs(&client, 100)
                 .await
                 .expect("failed to search song")
                 .len(),
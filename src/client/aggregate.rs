@@ -0,0 +1,97 @@
+use super::{SearchClient, SearchPage};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Result};
+use futures::future::join_all;
+use std::collections::HashMap;
+
+// Queries several backends at once so one outage or a lagging index doesn't hide maps
+pub struct AggregateClient {
+    clients: Vec<(String, Box<dyn SearchClient>)>,
+}
+
+impl AggregateClient {
+    pub fn new(clients: Vec<(String, Box<dyn SearchClient>)>) -> Self {
+        Self { clients }
+    }
+}
+
+#[async_trait]
+impl SearchClient for AggregateClient {
+    async fn fetch_page(&self, cursor: Option<String>) -> Result<SearchPage> {
+        self.fetch_page_since(cursor, None).await
+    }
+
+    // Each backend stops on its own once it passes `since`
+    async fn fetch_page_since(
+        &self,
+        cursor: Option<String>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<SearchPage> {
+        // The cursor keeps the next cursor of every backend that still has results
+        let cursors: HashMap<String, Option<String>> = match cursor {
+            Some(cursor) => serde_json::from_str(&cursor)?,
+            None => self
+                .clients
+                .iter()
+                .map(|(name, _)| (name.clone(), None))
+                .collect(),
+        };
+        let active = self
+            .clients
+            .iter()
+            .filter_map(|(name, client)| Some((name, client, cursors.get(name)?.clone())))
+            .collect::<Vec<_>>();
+        let results = join_all(
+            active
+                .iter()
+                .map(|(_, client, cursor)| client.fetch_page_since(cursor.clone(), since)),
+        )
+        .await;
+
+        let mut page = SearchPage::default();
+        let mut next = HashMap::new();
+        let mut index = HashMap::<u32, usize>::new();
+        let mut succeeded = Vec::new();
+        for ((name, _, _), result) in active.into_iter().zip(results) {
+            match result {
+                Ok(result) => {
+                    succeeded.push(name.as_str());
+                    if result.next.is_some() {
+                        next.insert(name.clone(), result.next);
+                    }
+                    page.failed.extend(result.failed);
+                    for song in result.songs {
+                        // Keep the first backend's order, fill in what it was missing
+                        match index.get(&song.id) {
+                            Some(i) => page.songs[*i].merge(song),
+                            None => {
+                                index.insert(song.id, page.songs.len());
+                                page.songs.push(song);
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::warn!("search backend {} failed: {}", name, e);
+                    // Leave it out of the following pages, the others carry on
+                    page.failed.push((name.clone(), e.to_string()));
+                }
+            }
+        }
+        if succeeded.is_empty() {
+            return Err(eyre!("all search backends failed"));
+        }
+        log::info!(
+            "found {} songs from {}",
+            page.songs.len(),
+            succeeded.join(", ")
+        );
+        page.next = if next.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&next)?)
+        };
+        Ok(page)
+    }
+}
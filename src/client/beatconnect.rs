@@ -0,0 +1,77 @@
+use super::{page_number, Beatmapset, Filter, Mode, SearchClient, SearchPage};
+use crate::ratelimit::RateLimiter;
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// beatconnect only answers searches with a personal API token
+pub struct BeatconnectClient {
+    client: reqwest::Client,
+    limiter: Arc<RateLimiter>,
+    token: String,
+    filter: Filter,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BeatconnectSearchResult {
+    pub beatmaps: Vec<Beatmapset>,
+    #[serde(default)]
+    pub max_page: u32,
+}
+
+impl BeatconnectClient {
+    pub fn new(limiter: Arc<RateLimiter>, token: String, filter: Filter) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            limiter,
+            token,
+            filter,
+        }
+    }
+
+    async fn search(&self, page: u32) -> Result<BeatconnectSearchResult> {
+        let url = format!(
+            "https://beatconnect.io/api/search/?token={}&m={}&s={}&p={}",
+            urlencoding::encode(&self.token),
+            match self.filter.mode {
+                Mode::Osu => "std",
+                Mode::Taiko => "taiko",
+                Mode::Fruits => "ctb",
+                Mode::Mania => "mania",
+            },
+            self.filter.status.name(),
+            page
+        );
+        let res = self.limiter.send(self.client.get(url)).await?;
+        if res.status().is_success() {
+            let result = res
+                .json::<BeatconnectSearchResult>()
+                .await
+                .map_err(|e| eyre!("can't map to beatmapset search result struct: {}", e))?;
+            Ok(result)
+        } else {
+            Err(eyre!("Request failed with status: {}", res.status()))
+        }
+    }
+}
+
+#[async_trait]
+impl SearchClient for BeatconnectClient {
+    async fn fetch_page(&self, cursor: Option<String>) -> Result<SearchPage> {
+        let page = page_number(cursor)?;
+        let res = self.search(page).await?;
+        let next =
+            (!res.beatmaps.is_empty() && page < res.max_page).then(|| (page + 1).to_string());
+        Ok(SearchPage {
+            songs: res.beatmaps,
+            next,
+            ..Default::default()
+        })
+    }
+
+    // Searches can't be ordered by ranked date, date searches read every page instead
+    fn sorted_by_ranked_date(&self) -> bool {
+        false
+    }
+}
@@ -1,21 +1,29 @@
-use super::{Beatmapset, SearchClient};
+use super::{page_number, Beatmapset, Filter, SearchClient, SearchPage};
+use crate::ratelimit::RateLimiter;
 use async_trait::async_trait;
 use eyre::{eyre, Ok, Result};
+use std::sync::Arc;
 
 pub struct NerinyanClient {
     client: reqwest::Client,
+    limiter: Arc<RateLimiter>,
+    filter: Filter,
 }
 
 impl NerinyanClient {
-    pub fn new() -> Self {
+    pub fn new(limiter: Arc<RateLimiter>, filter: Filter) -> Self {
         Self {
             client: reqwest::Client::new(),
+            limiter,
+            filter,
         }
     }
 
     async fn search(&self, page: u32) -> Result<Vec<Beatmapset>> {
         let url = format!(
-            "https://api.nerinyan.moe/search?m=0&s=ranked&nsfw=true&sort=ranked_desc&p={}&ps=50",
+            "https://api.nerinyan.moe/search?m={}&s={}&nsfw=true&sort=ranked_desc&p={}&ps=50",
+            self.filter.mode.id(),
+            self.filter.status.name(),
             page
         );
         let mut headers = reqwest::header::HeaderMap::new();
@@ -24,7 +32,10 @@ impl NerinyanClient {
             reqwest::header::HeaderValue::from_static("Mozilla/5.0"),
         );
 
-        let res = self.client.get(url).headers(headers).send().await?;
+        let res = self
+            .limiter
+            .send(self.client.get(url).headers(headers))
+            .await?;
         if res.status().is_success() {
             let result = res
                 .json::<Vec<Beatmapset>>()
@@ -39,13 +50,15 @@ impl NerinyanClient {
 
 #[async_trait]
 impl SearchClient for NerinyanClient {
-    async fn fetch_new_songs(&self, num: u32) -> Result<Vec<Beatmapset>> {
-        let n = num / 50;
-        let mut res = Vec::new();
-        for i in 0..n {
-            let r = self.search(i).await?;
-            res.extend(r);
-        }
-        Ok(res)
+    async fn fetch_page(&self, cursor: Option<String>) -> Result<SearchPage> {
+        let page = page_number(cursor)?;
+        let songs = self.search(page).await?;
+        // A short page means the end of the results
+        let next = (songs.len() == 50).then(|| (page + 1).to_string());
+        Ok(SearchPage {
+            songs,
+            next,
+            ..Default::default()
+        })
     }
 }
@@ -1,63 +1,101 @@
-use super::{Beatmapset, SearchClient};
+use super::{Beatmapset, Filter, SearchClient, SearchPage};
+use crate::auth::OsuAuth;
+use crate::ratelimit::RateLimiter;
+use crate::source::{get, API_URL};
 use crate::utils::empty_string_as_none;
 use async_trait::async_trait;
-use eyre::{eyre, Result};
+use eyre::Result;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const WEBSITE_SEARCH_URL: &str = "https://osu.ppy.sh/beatmapsets/search";
 
 pub struct OsuClient {
-    client: reqwest::Client,
+    limiter: Arc<RateLimiter>,
+    filter: Filter,
+    // The website's search, or the API's when there's `auth`
+    search_url: String,
+    auth: Option<Arc<OsuAuth>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct BeatmapsetSearchResult {
     pub beatmapsets: Vec<Beatmapset>,
-    #[serde(deserialize_with = "empty_string_as_none")]
+    #[serde(default, deserialize_with = "empty_string_as_none")]
     pub cursor_string: Option<String>,
 }
 
 impl OsuClient {
-    pub fn new() -> Self {
+    pub fn new(limiter: Arc<RateLimiter>, filter: Filter) -> Self {
+        Self {
+            limiter,
+            filter,
+            search_url: WEBSITE_SEARCH_URL.to_owned(),
+            auth: None,
+        }
+    }
+
+    // The API's search pages by the same cursor, page numbers stop after about 10k results
+    pub fn api(limiter: Arc<RateLimiter>, auth: Arc<OsuAuth>, filter: Filter) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            limiter,
+            filter,
+            search_url: format!("{}/beatmapsets/search", API_URL),
+            auth: Some(auth),
         }
     }
 
     async fn search(&self, cursor_string: Option<String>) -> Result<BeatmapsetSearchResult> {
         let url = format!(
-            "https://osu.ppy.sh/beatmapsets/search?m=0&s=ranked&nsfw=true&cursor_string={}&sort=ranked_desc",
-            if let Some(cursor_string) = cursor_string {
-                cursor_string
-            } else {
-                "".to_owned()
-            }
+            "{}?m={}&s={}&nsfw=true&cursor_string={}&sort=ranked_desc",
+            self.search_url,
+            self.filter.mode.id(),
+            self.filter.status.name(),
+            // Base64, with `+`, `/` and `=`
+            urlencoding::encode(cursor_string.as_deref().unwrap_or_default())
         );
-        let res = self.client.get(url).send().await?;
-        if res.status().is_success() {
-            let result = res
-                .json::<BeatmapsetSearchResult>()
-                .await
-                .map_err(|e| eyre!("can't map to beatmapset search result struct: {}", e))?;
-            Ok(result)
-        } else {
-            Err(eyre!("Request failed with status: {}", res.status()))
-        }
+        get(&self.limiter, self.auth.as_deref(), &url).await
     }
 }
 
 #[async_trait]
 impl SearchClient for OsuClient {
-    async fn fetch_new_songs(&self, num: u32) -> Result<Vec<Beatmapset>> {
-        let mut cursor = None;
-        let mut songs = Vec::new();
-        let n = num / 50;
-        for _ in 0..n {
-            let res = self.search(cursor).await?;
-            songs.extend(res.beatmapsets);
-            if res.cursor_string.is_none() {
-                break;
-            }
-            cursor = res.cursor_string;
-        }
-        Ok(songs)
+    async fn fetch_page(&self, cursor: Option<String>) -> Result<SearchPage> {
+        let res = self.search(cursor).await?;
+        Ok(SearchPage {
+            songs: res.beatmapsets,
+            next: res.cursor_string,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn test_search_cursor() {
+        let base_url = test_server::serve(vec![
+            (
+                "/search?m=0&s=ranked&nsfw=true&cursor_string=&sort=ranked_desc",
+                r#"{"beatmapsets": [{"id": 2, "title": "two"}], "cursor_string": "eyJpZCI6Mn0+/="}"#,
+            ),
+            (
+                "/search?m=0&s=ranked&nsfw=true&cursor_string=eyJpZCI6Mn0%2B%2F%3D&sort=ranked_desc",
+                r#"{"beatmapsets": [{"id": 1, "title": "one"}], "cursor_string": null}"#,
+            ),
+        ])
+        .await;
+        let client = OsuClient {
+            search_url: format!("{}/search", base_url),
+            ..OsuClient::new(Default::default(), Default::default())
+        };
+        let page = client.fetch_page(None).await.unwrap();
+        assert_eq!(page.songs[0].id, 2);
+        let page = client.fetch_page(page.next).await.unwrap();
+        assert_eq!(page.songs[0].id, 1);
+        assert_eq!(page.next, None);
     }
 }
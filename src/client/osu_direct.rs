@@ -0,0 +1,64 @@
+use super::{page_number, Beatmapset, Filter, SearchClient, SearchPage};
+use crate::ratelimit::RateLimiter;
+use async_trait::async_trait;
+use eyre::{eyre, Ok, Result};
+use std::sync::Arc;
+
+pub struct OsuDirectClient {
+    client: reqwest::Client,
+    limiter: Arc<RateLimiter>,
+    filter: Filter,
+}
+
+impl OsuDirectClient {
+    pub fn new(limiter: Arc<RateLimiter>, filter: Filter) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            limiter,
+            filter,
+        }
+    }
+
+    async fn search(&self, page: u32) -> Result<Vec<Beatmapset>> {
+        let url = format!(
+            "https://osu.direct/api/v2/search?mode={}&status={}&sort=ranked_date:desc&amount=50&offset={}",
+            self.filter.mode.id(),
+            self.filter.status.id(),
+            page * 50
+        );
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::USER_AGENT,
+            reqwest::header::HeaderValue::from_static("Mozilla/5.0"),
+        );
+
+        let res = self
+            .limiter
+            .send(self.client.get(url).headers(headers))
+            .await?;
+        if res.status().is_success() {
+            let result = res
+                .json::<Vec<Beatmapset>>()
+                .await
+                .map_err(|e| eyre!("can't map to beatmapset search result struct: {}", e))?;
+            Ok(result)
+        } else {
+            Err(eyre!("Request failed with status: {}", res.status()))
+        }
+    }
+}
+
+#[async_trait]
+impl SearchClient for OsuDirectClient {
+    async fn fetch_page(&self, cursor: Option<String>) -> Result<SearchPage> {
+        let page = page_number(cursor)?;
+        let songs = self.search(page).await?;
+        // A short page means the end of the results
+        let next = (songs.len() == 50).then(|| (page + 1).to_string());
+        Ok(SearchPage {
+            songs,
+            next,
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,324 @@
+use eyre::{eyre, Result};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+// What osu!stable writes in new files
+const DEFAULT_VERSION: i32 = 20150203;
+
+// osu!stable's collection.db: a version, then named lists of difficulty MD5 hashes
+#[derive(Debug, PartialEq)]
+pub struct CollectionDb {
+    pub version: i32,
+    pub collections: Vec<DbCollection>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbCollection {
+    pub name: String,
+    pub hashes: Vec<String>,
+}
+
+impl Default for CollectionDb {
+    fn default() -> Self {
+        Self {
+            version: DEFAULT_VERSION,
+            collections: Vec::new(),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if self.bytes.len() < n {
+            return Err(eyre!("collection.db ends unexpectedly"));
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn int(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn uleb128(&mut self) -> Result<usize> {
+        let mut value = 0usize;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(eyre!("collection.db has an invalid string length"))
+    }
+
+    // 0x00 for no string, or 0x0b followed by the ULEB128 length and UTF-8 bytes
+    fn string(&mut self) -> Result<String> {
+        match self.take(1)?[0] {
+            0x00 => Ok(String::new()),
+            0x0b => {
+                let len = self.uleb128()?;
+                Ok(String::from_utf8(self.take(len)?.to_vec())?)
+            }
+            other => Err(eyre!(
+                "collection.db has an invalid string marker {:#x}",
+                other
+            )),
+        }
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    if value.is_empty() {
+        out.push(0x00);
+        return;
+    }
+    out.push(0x0b);
+    let mut len = value.len();
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+    out.extend_from_slice(value.as_bytes());
+}
+
+impl CollectionDb {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        let version = reader.int()?;
+        let count = reader.int()?;
+        let mut collections = Vec::new();
+        for _ in 0..count {
+            let name = reader.string()?;
+            let len = reader.int()?;
+            let hashes = (0..len)
+                .map(|_| reader.string())
+                .collect::<Result<Vec<_>>>()?;
+            collections.push(DbCollection { name, hashes });
+        }
+        Ok(Self {
+            version,
+            collections,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&(self.collections.len() as i32).to_le_bytes());
+        for collection in &self.collections {
+            write_string(&mut out, &collection.name);
+            out.extend_from_slice(&(collection.hashes.len() as i32).to_le_bytes());
+            for hash in &collection.hashes {
+                write_string(&mut out, hash);
+            }
+        }
+        out
+    }
+
+    // A missing file is an empty database
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read(path)?).map_err(|e| eyre!("can't read '{}': {}", path.display(), e))
+    }
+
+    // Written next to the file first, so osu! never sees a partial one
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("db.tmp");
+        fs::write(&tmp, self.to_bytes())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    // Adds the hashes `name` doesn't have yet, creating it if needed. Returns how many were new.
+    pub fn add(&mut self, name: &str, hashes: &[String]) -> usize {
+        let index = match self.collections.iter().position(|c| c.name == name) {
+            Some(index) => index,
+            None => {
+                self.collections.push(DbCollection {
+                    name: name.to_owned(),
+                    hashes: Vec::new(),
+                });
+                self.collections.len() - 1
+            }
+        };
+        let collection = &mut self.collections[index];
+        let mut added = 0;
+        for hash in hashes {
+            if !collection.hashes.contains(hash) {
+                collection.hashes.push(hash.clone());
+                added += 1;
+            }
+        }
+        added
+    }
+}
+
+// Copy of the file as it was, like "collection.db.20240101-120000.bak"
+pub fn backup(path: &Path) -> Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    // Never overwrite an older backup taken in the same second
+    for n in 0.. {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        match n {
+            0 => name.push(format!(".{}.bak", stamp)),
+            n => name.push(format!(".{}-{}.bak", stamp, n)),
+        }
+        let backup = path.with_file_name(name);
+        if !backup.exists() {
+            fs::copy(path, &backup)?;
+            return Ok(Some(backup));
+        }
+    }
+    unreachable!()
+}
+
+fn is_osu_file(name: &str) -> bool {
+    name.to_ascii_lowercase().ends_with(".osu")
+}
+
+fn difficulty(contents: &[u8]) -> Difficulty {
+    let beatmap_id = String::from_utf8_lossy(contents).lines().find_map(|line| {
+        line.strip_prefix("BeatmapID:")
+            .and_then(|id| id.trim().parse().ok())
+    });
+    Difficulty {
+        beatmap_id,
+        hash: format!("{:x}", md5::compute(contents)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difficulty {
+    // Missing in old or unsubmitted difficulties
+    pub beatmap_id: Option<u32>,
+    pub hash: String,
+}
+
+// Each difficulty in a downloaded .osz or an extracted set folder
+pub fn difficulties(path: &Path) -> Result<Vec<Difficulty>> {
+    let mut difficulties = Vec::new();
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if is_osu_file(&entry.file_name().to_string_lossy()) {
+                difficulties.push(difficulty(&fs::read(entry.path())?));
+            }
+        }
+    } else {
+        let mut archive = ZipArchive::new(fs::File::open(path)?)
+            .map_err(|e| eyre!("{} is not a valid .osz: {}", path.display(), e))?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if !is_osu_file(&file.name()?) {
+                continue;
+            }
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            difficulties.push(difficulty(&contents));
+        }
+    }
+    difficulties.sort_by(|a, b| a.hash.cmp(&b.hash));
+    Ok(difficulties)
+}
+
+// Add each named list of hashes to its collection in the database at `path`, backing it up first.
+// Returns how many hashes were new.
+pub fn add_to_collections(path: &Path, collections: &[(String, Vec<String>)]) -> Result<usize> {
+    let mut db = CollectionDb::load(path)?;
+    let added = collections
+        .iter()
+        .map(|(name, hashes)| db.add(name, hashes))
+        .sum();
+    if added > 0 {
+        if let Some(backup) = backup(path)? {
+            log::info!("backed up '{}' to '{}'", path.display(), backup.display());
+        }
+        db.save(path)?;
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    #[test]
+    fn test_collection_db() {
+        let dir = std::env::temp_dir().join(format!("collection_db_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let osz = dir.join("1.osz");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&osz).unwrap());
+        zip.start_file("a.osu", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"osu file format v14\n[Metadata]\nBeatmapID:42\n")
+            .unwrap();
+        zip.start_file("audio.mp3", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"mp3").unwrap();
+        zip.finish().unwrap();
+        let difficulties = difficulties(&osz).unwrap();
+        let hash = format!(
+            "{:x}",
+            md5::compute(b"osu file format v14\n[Metadata]\nBeatmapID:42\n")
+        );
+        assert_eq!(
+            difficulties,
+            vec![Difficulty {
+                beatmap_id: Some(42),
+                hash: hash.clone(),
+            }]
+        );
+        let hashes = vec![hash];
+
+        let path = dir.join("collection.db");
+        let pool = vec![("Pool".to_owned(), hashes.clone())];
+        assert_eq!(add_to_collections(&path, &pool).unwrap(), 1);
+        assert_eq!(add_to_collections(&path, &pool).unwrap(), 0);
+        let long_name = "x".repeat(200);
+        let long = vec![(long_name.clone(), hashes.clone())];
+        assert_eq!(add_to_collections(&path, &long).unwrap(), 1);
+        let db = CollectionDb::load(&path).unwrap();
+        assert_eq!(db.version, DEFAULT_VERSION);
+        assert_eq!(
+            db.collections,
+            vec![
+                DbCollection {
+                    name: "Pool".to_owned(),
+                    hashes: hashes.clone(),
+                },
+                DbCollection {
+                    name: long_name,
+                    hashes: hashes.clone(),
+                },
+            ]
+        );
+        assert_eq!(CollectionDb::parse(&db.to_bytes()).unwrap(), db);
+        // The second write backed up the first
+        let backups = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == "bak")
+            .count();
+        assert_eq!(backups, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const COLLECTIONS_PATH: &str = "collections.yaml";
+
+// Named groups of maps from a source, kept to build in-game collections from
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct SavedCollections {
+    #[serde(default)]
+    pub collections: Vec<SavedCollection>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq)]
+pub struct SavedCollection {
+    pub name: String,
+    // Where the collection came from, like an osu!collector link
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub beatmapsets: Vec<u32>,
+    // MD5 of each difficulty, when the source has them
+    #[serde(default)]
+    pub checksums: Vec<String>,
+}
+
+impl SavedCollections {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = std::fs::File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_yaml::to_writer(file, self)?;
+        Ok(())
+    }
+
+    // A collection from the same source replaces the older copy
+    pub fn record(&mut self, collection: SavedCollection) {
+        match self
+            .collections
+            .iter_mut()
+            .find(|c| c.source == collection.source)
+        {
+            Some(saved) => *saved = collection,
+            None => self.collections.push(collection),
+        }
+    }
+}
@@ -1,116 +1,418 @@
+use crate::auth::{AuthKind, OsuAuth};
+use crate::client::{Beatmapset, Mode, Status};
+use crate::collection_db::{add_to_collections, difficulties};
+use crate::collections::{SavedCollection, SavedCollections, COLLECTIONS_PATH};
+use crate::estimate::{estimate_size, SizeEstimate};
+use crate::export::{
+    export, load_history, save_history, set_url, ExportEntry, ExportFormat, HISTORY_PATH,
+};
+use crate::extract::{extract_osz, osz_metadata};
+use crate::filename::{content_disposition_filename, sanitize_filename};
+use crate::hooks::{self, HookEvent, HookPayload};
+use crate::import::{import_list, ImportResult};
+use crate::mappool::{parse_mappool, resolve_mappool, ResolvedSlot};
+use crate::mirror::{by_priority, Mirror};
+use crate::ratelimit::RateLimiter;
+use crate::search::{self, Newest, SearchFailure, SearchLimit, SearchPosition, SearchUpdate};
+use crate::settings::{profile_path, Config, FetchMode};
+use crate::source::collector::CollectorClient;
+use crate::source::packs::{Pack, PackPage, PackSource, PackType};
+use crate::source::{user_beatmapsets, user_score_beatmapsets, UserCategory};
+use crate::strip::{strip_osz, StripOptions};
+use crate::sync::{missing, PendingSync, SyncState, SYNC_STATE_PATH};
+use crate::utils::format_size;
+use chrono::{DateTime, NaiveDate, Utc};
 use crossbeam::channel;
 use eframe::egui;
 use egui::{Grid, Hyperlink};
-use eyre::Result;
+use eyre::{eyre, Result};
 use reqwest::header::CONTENT_DISPOSITION;
-use std::collections::{HashMap, HashSet};
+use reqwest::{Method, Response};
+use rosu_v2::prelude::GameMode;
+use rosu_v2::Osu;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::io::Write;
-use std::path::Path;
-use std::path::PathBuf;
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::{Arc, RwLock};
+use std::future::Future;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 use strfmt::strfmt;
 use tokio::runtime::Runtime;
-use urlencoding;
+
+// How a download batch ended, picked up by the window once the workers are done
+struct BatchResult {
+    failed: usize,
+    stopped: bool,
+}
+
+// Pages between writes of sync_state.yaml during a search
+const SAVE_EVERY_PAGES: u32 = 10;
+
+// Packs listed so far, loaded a page at a time
+struct PackBrowser {
+    source: Arc<PackSource>,
+    kind: PackType,
+    packs: Vec<Pack>,
+    // Cursor of the following page and the type it belongs to
+    next: Option<(PackType, String)>,
+    is_loading: bool,
+    // The list was restarted while a page was loading, that page is dropped
+    reload: bool,
+    error: Option<String>,
+    page: Arc<RwLock<Option<LoadedPacks>>>,
+}
+
+// A page of packs and the type it was loaded for
+type LoadedPacks = (PackType, Result<PackPage, String>);
+
+// A tournament mappool, its sets are downloaded ahead of the rest of the queue
+struct Mappool {
+    text: String,
+    // Collections are named "{name} {pool}", or just the pool
+    name: String,
+    slots: Vec<ResolvedSlot>,
+    skipped: Vec<String>,
+    is_loading: bool,
+    error: Option<String>,
+    result: Arc<RwLock<Option<Vec<ResolvedSlot>>>>,
+}
+
+// Sets queued from one source, each panel has its own so they don't block each other
+#[derive(Default)]
+struct Import {
+    is_importing: bool,
+    message: Option<String>,
+    result: Arc<RwLock<Option<ImportResult>>>,
+}
+
+impl Import {
+    // Runs `fetch` in the background, see `finish_imports`
+    fn start<F>(&mut self, runtime: &Arc<Runtime>, description: String, fetch: F)
+    where
+        F: Future<Output = Result<ImportResult>> + Send + 'static,
+    {
+        if self.is_importing {
+            return;
+        }
+        self.is_importing = true;
+        self.message = None;
+        let runtime = runtime.clone();
+        let import_result = self.result.clone();
+        thread::spawn(move || {
+            let result = match runtime.block_on(fetch) {
+                Ok(result) => result,
+                Err(e) => ImportResult {
+                    skipped: vec![format!("{}: {}", description, e)],
+                    ..Default::default()
+                },
+            };
+            *import_result.write().unwrap() = Some(result);
+        });
+    }
+}
+
+// One in-game collection to fill from sets on disk and already known hashes
+struct CollectionRequest {
+    name: String,
+    sets: HashSet<u32>,
+    hashes: Vec<String>,
+    // Only these difficulties of the sets, for mappools
+    beatmaps: Option<HashSet<u32>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExportScope {
+    Found,
+    Selected,
+    Downloaded,
+}
 
 pub struct BeatmapDownloaderApp {
     number_of_fetch_songs: Arc<RwLock<u32>>,
     songs_path: String,
+    // Where downloads go, `songs_path` when empty
+    download_path: String,
+    filename_template: Option<String>,
     local_songs: Arc<RwLock<HashSet<u32>>>,
     new_songs: HashSet<u32>,
-    tx_control: Sender<bool>,
-    rx_update: Receiver<HashSet<u32>>,
+    // Songs ticked in the list, for exporting part of it
+    selected: HashSet<u32>,
+    // Sets downloaded, earlier sessions first
+    history: Arc<RwLock<Vec<ExportEntry>>>,
+    // How many `history` entries come from earlier sessions
+    history_start: usize,
+    export_scope: ExportScope,
+    export_format: ExportFormat,
+    export_message: Option<String>,
+    // Search metadata of the new songs, for file names
+    song_info: HashMap<u32, Beatmapset>,
+    tx_control: Sender<SearchPosition>,
+    rx_update: Receiver<SearchUpdate>,
     is_fetching: bool,
+    search_warnings: Vec<String>,
+    search_failure: Option<SearchFailure>,
+    // Mode and start time of the running search
+    current_search: Option<(FetchMode, DateTime<Utc>)>,
+    // Position of the next page of the running search
+    search_position: Option<SearchPosition>,
+    // Key of the search's checkpoint and backfill in `sync_state`
+    sync_key: String,
+    fetch_mode: FetchMode,
+    since_date: String,
+    sync_state: SyncState,
     is_download: bool,
     is_download_finish: Arc<RwLock<bool>>,
+    batch_result: Arc<RwLock<Option<BatchResult>>>,
+    // Start of a complete date search, saved as `last_sync` once its sets are downloaded
+    pending_last_sync: Option<DateTime<Utc>>,
+    // Newest set of a complete incremental sync, the checkpoint once its sets are downloaded
+    pending_checkpoint: Option<Newest>,
     selected_server: String,
     number_of_simultaneous_downloads: u64,
-    server: HashMap<String, String>,
+    no_video: bool,
+    // Unpack downloads into folders of `songs_path`
+    extract: bool,
+    strip: StripOptions,
+    // Bytes removed by stripping assets in the current batch
+    space_saved: Arc<AtomicU64>,
+    // Events for the hook thread
+    hooks: Sender<HookPayload>,
+    server: HashMap<String, Mirror>,
     runtime: Arc<Runtime>,
+    limiter: Arc<RateLimiter>,
+    auth: Arc<OsuAuth>,
+    // New osu_session cookie typed into the window, saved to the OS keyring
+    session_cookie: String,
+    has_session_cookie: bool,
+    credentials_message: Option<String>,
     percentage: Arc<RwLock<HashMap<u32, Arc<RwLock<f32>>>>>,
+    size_estimate: Arc<RwLock<Option<Result<SizeEstimate, String>>>>,
+    // Mirror and sets `size_estimate` is for, kept until the search changes
+    estimated: Option<(String, HashSet<u32>)>,
+    // Mirrors that answered without sizes, downloads from them aren't estimated
+    unsized_mirrors: HashSet<String>,
+    // Why the batch may not fit, until the user confirms or searches again
+    disk_space_warning: Option<String>,
+    is_estimating: bool,
+    // Download clicked, started once the size estimate is in
+    download_requested: bool,
+    // Pasted list of maps to queue
+    import_text: String,
+    list_import: Import,
+    user_import: Import,
+    pack_import: Import,
+    collection_import: Import,
+    // Username or ID whose profile lists are fetched
+    source_user: String,
+    user_category: UserCategory,
+    score_mode: GameMode,
+    scores_best: bool,
+    scores_recent: bool,
+    packs: PackBrowser,
+    collector: Arc<CollectorClient>,
+    mappool: Mappool,
+    // Sets the running batch's workers take before the rest of its queue, like a mappool
+    urgent: Arc<Mutex<VecDeque<u32>>>,
+    collection_db: PathBuf,
+    // In-game collection the downloaded batch is added to
+    collection_name: String,
+    is_writing_collection: Arc<AtomicBool>,
+    collection_message: Arc<RwLock<Option<String>>>,
+    // osu!collector collection ID or link
+    collection_input: String,
+    // collections.yaml, written back when a collection is fetched
+    saved_collections: SavedCollections,
+    // API client for score lookups, created on first use
+    osu: Arc<tokio::sync::OnceCell<Osu>>,
+    client_id: u64,
+    client_secret: String,
+    // Error that stopped the whole batch, like a full disk
+    download_error: Arc<RwLock<Option<String>>>,
+    profile: Option<String>,
+    profiles: Vec<String>,
+    // The profile's own copies of the state files
+    history_path: PathBuf,
+    sync_state_path: PathBuf,
+    collections_path: PathBuf,
+    // Profile to reopen the window with, read by `main` once it closes
+    profile_switch: Arc<RwLock<Option<String>>>,
 }
 
 impl BeatmapDownloaderApp {
     pub fn new(
         runtime: Arc<Runtime>,
+        limiter: Arc<RateLimiter>,
+        auth: Arc<OsuAuth>,
         search_client: Box<dyn crate::client::SearchClient>,
-        songs_path: String,
-        number_of_fetch: u32,
-        server: HashMap<String, String>,
-        selected_server: String,
-        number_of_simultaneous_downloads: u64,
+        config: Config,
+        profile_switch: Arc<RwLock<Option<String>>>,
     ) -> Box<Self> {
-        let (tx_update, rx_update) = mpsc::channel::<HashSet<u32>>();
-        let (tx_control, rx_control) = mpsc::channel::<bool>();
+        let (tx_update, rx_update) = mpsc::channel::<SearchUpdate>();
+        let (tx_control, rx_control) = mpsc::channel::<SearchPosition>();
         let local_songs = Arc::new(RwLock::new(HashSet::<u32>::new()));
         let local_songs_clone = local_songs.clone();
-        let number_of_fetch_songs = Arc::new(RwLock::<u32>::new(number_of_fetch));
-        let number_of_fetch_songs_clone = number_of_fetch_songs.clone();
+        let number_of_fetch_songs = Arc::new(RwLock::<u32>::new(config.number_of_fetch));
+        let (tx_hooks, rx_hooks) = mpsc::channel::<HookPayload>();
+        let runtime_clone = runtime.clone();
+        let config_hooks = config.hooks.clone();
+        thread::spawn(move || hooks::run_worker(runtime_clone, config_hooks, rx_hooks));
         let runtime_clone = runtime.clone();
         // Spawn the background thread
         thread::spawn(move || {
-            Self::background_process(
+            search::run_worker(
                 runtime_clone,
                 search_client,
                 rx_control,
                 tx_update,
                 local_songs_clone,
-                number_of_fetch_songs_clone,
             );
         });
 
+        let pack_source = Arc::new(PackSource::new(
+            config.pack_source.clone(),
+            limiter.clone(),
+            auth.clone(),
+        ));
+        let collector = Arc::new(CollectorClient::new(
+            config.collector_url.clone(),
+            limiter.clone(),
+        ));
+        let collection_db = match &config.collection_db {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&config.songs_path)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join("collection.db"),
+        };
+        let has_session_cookie = runtime.block_on(auth.has_session_cookie());
+        let history_path = profile_path(HISTORY_PATH, config.profile.as_deref());
+        let history = load_history(&history_path).unwrap_or_else(|e| {
+            log::warn!("failed to read download history: {}", e);
+            Vec::new()
+        });
+        let sync_state_path = profile_path(SYNC_STATE_PATH, config.profile.as_deref());
+        let collections_path = profile_path(COLLECTIONS_PATH, config.profile.as_deref());
         let mut app = Self {
-            number_of_fetch_songs: number_of_fetch_songs,
-            songs_path: songs_path,
-            local_songs: local_songs,
+            number_of_fetch_songs,
+            songs_path: config.songs_path,
+            download_path: config.download_path.unwrap_or_default(),
+            filename_template: config.filename_template,
+            local_songs,
             new_songs: HashSet::new(),
+            selected: HashSet::new(),
+            history_start: history.len(),
+            history: Arc::new(RwLock::new(history)),
+            export_scope: ExportScope::Found,
+            export_format: ExportFormat::Text,
+            export_message: None,
+            song_info: HashMap::new(),
             tx_control,
             rx_update,
             is_fetching: false,
+            search_warnings: Vec::new(),
+            search_failure: None,
+            current_search: None,
+            search_position: None,
+            sync_key: sync_key(&config.search_client, config.mode, config.status),
+            fetch_mode: config.fetch_mode,
+            since_date: config
+                .since_date
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            sync_state: SyncState::load(&sync_state_path).unwrap_or_else(|e| {
+                log::warn!("failed to read sync state: {}", e);
+                SyncState::default()
+            }),
             is_download: false,
             is_download_finish: Arc::new(RwLock::new(true)),
-            selected_server: selected_server,
-            server: server,
-            runtime: runtime,
+            batch_result: Arc::new(RwLock::new(None)),
+            pending_last_sync: None,
+            pending_checkpoint: None,
+            selected_server: config.selected_server,
+            no_video: config.no_video,
+            extract: config.extract,
+            strip: StripOptions {
+                video: config.strip_video,
+                storyboard: config.strip_storyboard,
+            },
+            space_saved: Arc::new(AtomicU64::new(0)),
+            hooks: tx_hooks,
+            server: config.server,
+            runtime,
+            limiter,
+            auth,
+            session_cookie: String::new(),
+            has_session_cookie,
+            credentials_message: None,
             percentage: Arc::new(RwLock::new(HashMap::<u32, Arc<RwLock<f32>>>::new())),
-            number_of_simultaneous_downloads,
+            size_estimate: Arc::new(RwLock::new(None)),
+            estimated: None,
+            unsized_mirrors: HashSet::new(),
+            disk_space_warning: None,
+            is_estimating: false,
+            download_requested: false,
+            import_text: String::new(),
+            list_import: Import::default(),
+            user_import: Import::default(),
+            pack_import: Import::default(),
+            collection_import: Import::default(),
+            source_user: String::new(),
+            user_category: UserCategory::Favourite,
+            score_mode: GameMode::Osu,
+            scores_best: true,
+            scores_recent: false,
+            packs: PackBrowser {
+                source: pack_source,
+                kind: PackType::Standard,
+                packs: Vec::new(),
+                next: None,
+                is_loading: false,
+                reload: false,
+                error: None,
+                page: Arc::new(RwLock::new(None)),
+            },
+            collector,
+            collection_input: String::new(),
+            saved_collections: SavedCollections::load(&collections_path).unwrap_or_else(|e| {
+                log::warn!("failed to read saved collections: {}", e);
+                SavedCollections::default()
+            }),
+            mappool: Mappool {
+                text: String::new(),
+                name: String::new(),
+                slots: Vec::new(),
+                skipped: Vec::new(),
+                is_loading: false,
+                error: None,
+                result: Arc::new(RwLock::new(None)),
+            },
+            urgent: Arc::new(Mutex::new(VecDeque::new())),
+            collection_db,
+            collection_name: String::new(),
+            is_writing_collection: Arc::new(AtomicBool::new(false)),
+            collection_message: Arc::new(RwLock::new(None)),
+            osu: Arc::new(tokio::sync::OnceCell::new()),
+            client_id: config.client_id,
+            client_secret: config.client_secret.clone(),
+            download_error: Arc::new(RwLock::new(None)),
+            profile: config.profile,
+            profiles: config.profiles.into_keys().collect(),
+            history_path,
+            sync_state_path,
+            collections_path,
+            profile_switch,
+            number_of_simultaneous_downloads: config.number_of_simultaneous_downloads,
         };
         app.load_songs_from_local();
 
         Box::new(app)
     }
 
-    // The background process logic
-    fn background_process(
-        runtime: Arc<Runtime>,
-        search_client: Box<dyn crate::client::SearchClient>,
-        rx: Receiver<bool>,
-        tx: Sender<HashSet<u32>>,
-        local_songs: Arc<RwLock<HashSet<u32>>>,
-        number_of_fetch_songs: Arc<RwLock<u32>>,
-    ) {
-        loop {
-            // Check for incoming commands
-            if let Ok(_) = rx.try_recv() {
-                let mut new_songs = HashSet::new();
-                let n: u32 = *number_of_fetch_songs.read().unwrap(); // copy value
-                let result = runtime.block_on(search_client.fetch_new_songs(n)).unwrap();
-                for song in result.iter() {
-                    if !local_songs.read().unwrap().contains(&song.id) {
-                        new_songs.insert(song.id);
-                    }
-                }
-                let _ = tx.send(new_songs);
-            }
-            // Sleep to simulate work and avoid busy-waiting
-            thread::sleep(Duration::from_millis(10));
-        }
-    }
-
-    fn extract_song_id(path: &PathBuf) -> Option<u32> {
+    fn extract_song_id(path: &Path) -> Option<u32> {
         path.file_name()
             .and_then(|f| f.to_str())
             .and_then(|song| song.split_whitespace().next())
@@ -127,16 +429,77 @@ impl BeatmapDownloaderApp {
                 self.local_songs.write().unwrap().insert(song_id);
             }
         }
+        // Archives still waiting in a staging folder count as local too
+        if self.download_dir() != self.songs_path {
+            if let Ok(entries) = fs::read_dir(self.download_dir()) {
+                for entry in entries.flatten() {
+                    if let Some(song_id) = Self::extract_song_id(&entry.path()) {
+                        self.local_songs.write().unwrap().insert(song_id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn download_dir(&self) -> &str {
+        if self.download_path.trim().is_empty() {
+            &self.songs_path
+        } else {
+            &self.download_path
+        }
     }
 
     fn list_new_songs(&mut self, ui: &mut egui::Ui) {
-        if let Ok(new_songs) = self.rx_update.try_recv() {
-            self.is_fetching = false;
-            self.new_songs = new_songs;
-            self.is_download = false;
-            for song in self.new_songs.iter() {
-                let mut p = self.percentage.write().unwrap();
-                p.insert(*song, Arc::new(RwLock::new(0.0)));
+        loop {
+            let update = match self.rx_update.try_recv() {
+                Ok(update) => update,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if self.is_fetching {
+                        self.is_fetching = false;
+                        self.search_warnings
+                            .push("search worker stopped".to_owned());
+                    }
+                    break;
+                }
+            };
+            match update {
+                SearchUpdate::Page(mut new_songs, seen) => {
+                    if let Some((FetchMode::Incremental, _)) = self.current_search {
+                        let checkpoint = self
+                            .sync_state
+                            .checkpoints
+                            .entry(self.sync_key.clone())
+                            .or_default();
+                        // The date limit includes the checkpoint set itself
+                        new_songs.retain(|song| Some(song.id) != checkpoint.id);
+                        let pending = checkpoint.pending.get_or_insert_with(Default::default);
+                        pending.found.extend(new_songs.iter().map(|song| song.id));
+                    }
+                    if let Some((FetchMode::Backfill, _)) = self.current_search {
+                        let backfill = self
+                            .sync_state
+                            .backfills
+                            .entry(self.sync_key.clone())
+                            .or_default();
+                        backfill.found.extend(new_songs.iter().map(|song| song.id));
+                        backfill.seen += seen;
+                    }
+                    self.add_new_songs(new_songs.iter().map(|song| song.id));
+                    self.add_song_info(new_songs);
+                }
+                SearchUpdate::Position(position) => self.save_position(position),
+                SearchUpdate::Partial(warning) => self.search_warnings.push(warning),
+                SearchUpdate::Failed(failure) => {
+                    self.search_failure = Some(failure);
+                    self.save_sync_state();
+                }
+                SearchUpdate::Done => {
+                    self.is_fetching = false;
+                    if self.search_failure.is_none() {
+                        self.finish_search();
+                    }
+                }
             }
         }
 
@@ -154,18 +517,24 @@ impl BeatmapDownloaderApp {
                         .show(ui, |ui| {
                             for song in self.new_songs.iter() {
                                 ui.set_min_width(6000.0);
-                                ui.label(format!("{}", song));
-                                let fmt = self.server.get(&self.selected_server).unwrap();
-                                let mut selected_server = HashMap::<String, u32>::new();
-                                selected_server.insert("beatmap_id".to_string(), *song);
-                                let result = strfmt(fmt, &selected_server).unwrap();
-                                ui.add(Hyperlink::new(result));
-                                if self.is_download {
-                                    let percentage_rw = self.percentage.read().unwrap();
-                                    let percentage =
-                                        percentage_rw.get(song).unwrap().read().unwrap();
+                                let mut selected = self.selected.contains(song);
+                                if ui.checkbox(&mut selected, song.to_string()).changed() {
+                                    if selected {
+                                        self.selected.insert(*song);
+                                    } else {
+                                        self.selected.remove(song);
+                                    }
+                                }
+                                let mirror = self.server.get(&self.selected_server).unwrap();
+                                if let Ok(url) = mirror.url_for(*song, self.no_video) {
+                                    ui.add(Hyperlink::new(url));
+                                }
+                                let percentage_rw = self.percentage.read().unwrap();
+                                let percentage =
+                                    percentage_rw.get(song).map_or(0.0, |p| *p.read().unwrap());
+                                if self.is_download || percentage > 0.0 {
                                     ui.add(
-                                        egui::ProgressBar::new(*percentage)
+                                        egui::ProgressBar::new(percentage)
                                             .show_percentage()
                                             .animate(true),
                                     );
@@ -177,61 +546,986 @@ impl BeatmapDownloaderApp {
             });
     }
 
+    fn add_new_songs(&mut self, songs: impl IntoIterator<Item = u32>) {
+        let mut p = self.percentage.write().unwrap();
+        for song in songs {
+            p.entry(song).or_insert_with(|| Arc::new(RwLock::new(0.0)));
+            self.new_songs.insert(song);
+        }
+    }
+
+    fn search_position(&self) -> Result<SearchPosition> {
+        let pages = *self.number_of_fetch_songs.read().unwrap() / 50;
+        let limit = match self.fetch_mode {
+            FetchMode::Pages => SearchLimit::Pages(pages),
+            FetchMode::SinceDate => {
+                let date = NaiveDate::parse_from_str(self.since_date.trim(), "%Y-%m-%d")
+                    .map_err(|_| eyre!("'{}' is not a YYYY-MM-DD date", self.since_date))?;
+                SearchLimit::Since(date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+            }
+            FetchMode::SinceLastSync => self
+                .sync_state
+                .last_sync
+                .map(SearchLimit::Since)
+                .ok_or_else(|| eyre!("no previous sync, search since a date first"))?,
+            FetchMode::Incremental => {
+                let checkpoint = self
+                    .sync_state
+                    .checkpoints
+                    .get(&self.sync_key)
+                    .cloned()
+                    .unwrap_or_default();
+                // Without a checkpoint yet, the first sync takes the usual number of pages
+                let limit = match (checkpoint.ranked_date, checkpoint.id) {
+                    (Some(ranked_date), _) => SearchLimit::Since(ranked_date),
+                    (None, Some(id)) => SearchLimit::UntilId(id),
+                    (None, None) => SearchLimit::Pages(pages),
+                };
+                if let Some(pending) = checkpoint.pending {
+                    return Ok(SearchPosition::resume(pending, limit));
+                }
+                limit
+            }
+            FetchMode::Backfill => {
+                let backfill = self.sync_state.backfills.get(&self.sync_key);
+                if let Some(pending) = backfill.and_then(|backfill| backfill.pending.clone()) {
+                    return Ok(SearchPosition::resume(pending, SearchLimit::All));
+                }
+                SearchLimit::All
+            }
+        };
+        Ok(SearchPosition::start(limit))
+    }
+
     fn find_new_songs(&mut self) {
         if !self.is_fetching {
+            // Workers of a running batch share this map, it's cleared rather than replaced
+            self.percentage.write().unwrap().clear();
+            self.new_songs.clear();
+            self.selected.clear();
+            self.song_info.clear();
+            self.search_warnings.clear();
+            self.search_failure = None;
+            let position = match self.search_position() {
+                Ok(position) => position,
+                Err(e) => {
+                    self.search_warnings.push(e.to_string());
+                    return;
+                }
+            };
+            *self.size_estimate.write().unwrap() = None;
+            self.disk_space_warning = None;
+            if self.fetch_mode == FetchMode::Backfill {
+                let backfill = self
+                    .sync_state
+                    .backfills
+                    .entry(self.sync_key.clone())
+                    .or_default();
+                if backfill.pending.is_none() {
+                    // The previous backfill finished, walk the catalogue again
+                    backfill.found.clear();
+                    backfill.seen = 0;
+                }
+                // Sets found before the backfill was interrupted
+                let found = missing(&backfill.found, &self.local_songs.read().unwrap());
+                self.add_new_songs(found);
+            }
+            if self.fetch_mode == FetchMode::Incremental {
+                // Sets found on the pages before an interrupted sync stopped
+                let pending = self
+                    .sync_state
+                    .checkpoints
+                    .get(&self.sync_key)
+                    .and_then(|checkpoint| checkpoint.pending.as_ref());
+                if let Some(pending) = pending {
+                    let found = missing(&pending.found, &self.local_songs.read().unwrap());
+                    self.add_new_songs(found);
+                }
+            }
             self.is_fetching = true;
-            let _ = self.tx_control.send(true);
+            self.current_search = Some((self.fetch_mode, Utc::now()));
+            self.search_position = Some(position.clone());
+            let _ = self.tx_control.send(position);
+        }
+    }
+
+    fn save_history(&self) {
+        if let Err(e) = save_history(&self.history_path, &self.history.read().unwrap()) {
+            log::warn!("failed to save download history: {}", e);
+        }
+    }
+
+    fn save_sync_state(&self) {
+        if let Err(e) = self.sync_state.save(&self.sync_state_path) {
+            log::warn!("failed to save sync state: {}", e);
+        }
+    }
+
+    // Incremental syncs and backfills remember their progress so an interrupted one picks up where
+    // it stopped. The found sets grow with every page, so they're written every few pages.
+    fn save_position(&mut self, position: SearchPosition) {
+        let save = position.page.is_multiple_of(SAVE_EVERY_PAGES);
+        match self.current_search {
+            Some((FetchMode::Incremental, _)) => {
+                let checkpoint = self
+                    .sync_state
+                    .checkpoints
+                    .entry(self.sync_key.clone())
+                    .or_default();
+                let found = checkpoint
+                    .pending
+                    .take()
+                    .map(|pending| pending.found)
+                    .unwrap_or_default();
+                checkpoint.pending = Some(PendingSync {
+                    found,
+                    ..position.pending()
+                });
+                if save {
+                    self.save_sync_state();
+                }
+            }
+            Some((FetchMode::Backfill, _)) => {
+                let backfill = self
+                    .sync_state
+                    .backfills
+                    .entry(self.sync_key.clone())
+                    .or_default();
+                backfill.pending = Some(position.pending());
+                if save {
+                    self.save_sync_state();
+                }
+            }
+            _ => (),
+        }
+        self.search_position = Some(position);
+    }
+
+    fn finish_search(&mut self) {
+        let (Some((mode, started)), Some(position)) =
+            (self.current_search.take(), self.search_position.take())
+        else {
+            return;
+        };
+        // A complete date search covers everything ranked until it started, but only once
+        // every backend answered and the sets it found are on disk
+        self.pending_last_sync = None;
+        if let SearchLimit::Since(_) = position.limit {
+            if !self.search_warnings.is_empty() {
+                log::warn!("not saving the sync time, part of the search failed");
+            } else if self.new_songs.is_empty() {
+                self.sync_state.last_sync = Some(started);
+            } else {
+                self.pending_last_sync = Some(started);
+            }
+        }
+        self.pending_checkpoint = None;
+        if mode == FetchMode::Incremental {
+            self.sync_state
+                .checkpoints
+                .entry(self.sync_key.clone())
+                .or_default()
+                .pending = None;
+            // The gap stays open until its sets are downloaded, a search that stops before then
+            // walks it again
+            match position.newest {
+                Some(newest) if self.new_songs.is_empty() => self.move_checkpoint(newest),
+                newest => self.pending_checkpoint = newest,
+            }
+        }
+        if mode == FetchMode::Backfill {
+            let backfill = self
+                .sync_state
+                .backfills
+                .entry(self.sync_key.clone())
+                .or_default();
+            backfill.pending = None;
+            // Songs may have been added locally since earlier pages, diff against the library as it is now
+            self.new_songs = missing(&backfill.found, &self.local_songs.read().unwrap())
+                .into_iter()
+                .collect();
+        }
+        self.save_sync_state();
+    }
+
+    fn move_checkpoint(&mut self, newest: Newest) {
+        let checkpoint = self
+            .sync_state
+            .checkpoints
+            .entry(self.sync_key.clone())
+            .or_default();
+        checkpoint.id = Some(newest.id);
+        checkpoint.ranked_date = newest.ranked_date.or(checkpoint.ranked_date);
+    }
+
+    // Sets this session's batches downloaded. Earlier sessions don't count, their files may be gone.
+    fn downloaded(&self) -> HashSet<u32> {
+        let history = self.history.read().unwrap();
+        history[self.history_start..]
+            .iter()
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    // Found sets still missing, earlier batches and sets added to the library since don't count
+    fn queued(&self) -> Vec<u32> {
+        let downloaded = self.downloaded();
+        queue(
+            &self.new_songs,
+            &self.local_songs.read().unwrap(),
+            &downloaded,
+        )
+    }
+
+    fn finish_batch(&mut self) {
+        let Some(result) = self.batch_result.write().unwrap().take() else {
+            return;
+        };
+        // Sets queued from now on start a new batch
+        self.is_download = false;
+        self.save_history();
+        if let Some(newest) = self.pending_checkpoint.take() {
+            if result.failed == 0 && !result.stopped {
+                self.move_checkpoint(newest);
+                self.save_sync_state();
+            } else {
+                log::warn!("not moving the sync checkpoint, some downloads failed");
+            }
+        }
+        if let Some(last_sync) = self.pending_last_sync.take() {
+            if result.failed == 0 && !result.stopped {
+                self.sync_state.last_sync = Some(last_sync);
+                self.save_sync_state();
+            } else {
+                log::warn!("not saving the sync time, some downloads failed");
+            }
+        }
+        // Urgent sets that came in after the workers had run out of work.
+        // A stopped batch leaves them in the list for the next one.
+        let urgent = self.urgent.lock().unwrap().drain(..).collect::<Vec<_>>();
+        let urgent = urgent
+            .into_iter()
+            .filter(|id| !self.is_ready(*id))
+            .collect::<Vec<_>>();
+        if !urgent.is_empty() && !result.stopped {
+            self.download_v2(urgent);
         }
     }
 
-    fn download_v2(&mut self) {
+    // Sample the selected mirror in the background to estimate the download size
+    fn estimate_size(&mut self) {
+        let Some(mirror) = self.server.get(&self.selected_server).cloned() else {
+            return;
+        };
+        *self.size_estimate.write().unwrap() = None;
+        self.is_estimating = true;
+        let ids = self.queued();
+        self.estimated = Some((self.selected_server.clone(), ids.iter().copied().collect()));
+        let runtime = self.runtime.clone();
+        let limiter = self.limiter.clone();
+        let auth = self.auth.clone();
+        let no_video = self.no_video;
+        let size_estimate = self.size_estimate.clone();
+        thread::spawn(move || {
+            let result = runtime
+                .block_on(estimate_size(&limiter, &auth, &mirror, no_video, &ids, 20))
+                .map_err(|e| e.to_string());
+            *size_estimate.write().unwrap() = Some(result);
+        });
+    }
+
+    // Beatmaps are resolved to their sets in the background
+    fn import(&mut self, text: String) {
+        if text.trim().is_empty() {
+            return;
+        }
+        let limiter = self.limiter.clone();
+        self.list_import
+            .start(&self.runtime, "list".to_owned(), async move {
+                Ok(import_list(&limiter, &text).await)
+            });
+    }
+
+    fn fetch_user_maps(&mut self) {
+        if self.source_user.trim().is_empty() {
+            return;
+        }
+        let limiter = self.limiter.clone();
+        let auth = self.auth.clone();
+        let user = self.source_user.clone();
+        let category = self.user_category;
+        let description = format!("{} of {}", category.label(), user);
+        self.user_import
+            .start(&self.runtime, description, async move {
+                let songs = user_beatmapsets(&limiter, &auth, &user, category).await?;
+                Ok(ImportResult::from_songs(songs))
+            });
+    }
+
+    fn fetch_user_scores(&mut self) {
+        if self.source_user.trim().is_empty() || !(self.scores_best || self.scores_recent) {
+            return;
+        }
+        let osu = self.osu.clone();
+        let client_id = self.client_id;
+        let client_secret = self.client_secret.clone();
+        let user = self.source_user.clone();
+        let (mode, best, recent) = (self.score_mode, self.scores_best, self.scores_recent);
+        let description = format!("scores of {}", user);
+        self.user_import
+            .start(&self.runtime, description, async move {
+                let osu = osu
+                    .get_or_try_init(|| Osu::new(client_id, client_secret))
+                    .await?;
+                let songs = user_score_beatmapsets(osu, &user, mode, best, recent).await?;
+                Ok(ImportResult::from_songs(songs))
+            });
+    }
+
+    // Next page of packs, or the first one when `restart`
+    fn load_packs(&mut self, restart: bool) {
+        if restart {
+            self.packs.packs.clear();
+            self.packs.next = None;
+            self.packs.error = None;
+        }
+        if self.packs.is_loading {
+            // Started again once the page in flight is back
+            self.packs.reload |= restart;
+            return;
+        }
+        let kind = self.packs.kind;
+        let cursor = match self.packs.next.clone() {
+            Some((next_kind, cursor)) if next_kind == kind => Some(cursor),
+            _ if restart => None,
+            _ => return,
+        };
+        self.packs.is_loading = true;
+        self.packs.error = None;
+        let runtime = self.runtime.clone();
+        let source = self.packs.source.clone();
+        let page = self.packs.page.clone();
+        thread::spawn(move || {
+            let result = runtime
+                .block_on(source.list(kind, cursor))
+                .map_err(|e| e.to_string());
+            *page.write().unwrap() = Some((kind, result));
+        });
+    }
+
+    fn finish_packs(&mut self) {
+        let Some((kind, result)) = self.packs.page.write().unwrap().take() else {
+            return;
+        };
+        self.packs.is_loading = false;
+        if std::mem::take(&mut self.packs.reload) || kind != self.packs.kind {
+            self.load_packs(true);
+            return;
+        }
+        match result {
+            Ok(page) => {
+                self.packs.packs.extend(page.packs);
+                self.packs.next = page.cursor_string.map(|cursor| (kind, cursor));
+            }
+            Err(e) => self.packs.error = Some(e),
+        }
+    }
+
+    // Only the pack's sets that aren't local get queued, not the pack archive
+    fn queue_pack(&mut self, pack: Pack) {
+        let source = self.packs.source.clone();
+        self.pack_import
+            .start(&self.runtime, pack.name.clone(), async move {
+                Ok(ImportResult::from_songs(
+                    source.beatmapsets(&pack.tag).await?,
+                ))
+            });
+    }
+
+    // The collection's name and maps are kept for building the in-game collection
+    fn fetch_collection(&mut self) {
+        let input = self.collection_input.trim().to_owned();
+        if input.is_empty() {
+            return;
+        }
+        let collector = self.collector.clone();
+        let description = format!("collection {}", input);
+        self.collection_import
+            .start(&self.runtime, description, async move {
+                let collection = collector.collection(&input).await?;
+                Ok(ImportResult {
+                    sets: collection.beatmapsets.clone(),
+                    songs: collection.songs,
+                    collection: Some(SavedCollection {
+                        name: collection.name,
+                        source: format!("osucollector:{}", collection.id),
+                        beatmapsets: collection.beatmapsets,
+                        checksums: collection.checksums,
+                    }),
+                    ..Default::default()
+                })
+            });
+    }
+
+    // Slots are resolved to sets in the background, see `finish_mappool`
+    fn load_mappool(&mut self, text: String) {
+        if self.mappool.is_loading {
+            return;
+        }
+        self.mappool.error = None;
+        let (slots, skipped) = match parse_mappool(&text) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.mappool.error = Some(e.to_string());
+                return;
+            }
+        };
+        self.mappool.skipped = skipped;
+        self.mappool.is_loading = true;
+        let runtime = self.runtime.clone();
+        let limiter = self.limiter.clone();
+        let result = self.mappool.result.clone();
+        thread::spawn(move || {
+            let slots = runtime.block_on(resolve_mappool(&limiter, slots));
+            *result.write().unwrap() = Some(slots);
+        });
+    }
+
+    fn is_ready(&self, set_id: u32) -> bool {
+        self.local_songs.read().unwrap().contains(&set_id) || self.downloaded().contains(&set_id)
+    }
+
+    // Queue the pool's missing sets first and start downloading them
+    fn finish_mappool(&mut self) {
+        let Some(slots) = self.mappool.result.write().unwrap().take() else {
+            return;
+        };
+        self.mappool.is_loading = false;
+        let missing = pool_missing(&slots, |id| self.is_ready(id));
+        self.mappool.slots = slots;
+        if missing.is_empty() {
+            return;
+        }
+        self.add_new_songs(missing.iter().copied());
+        // A running batch takes the pool next, otherwise it's a batch of its own.
+        // A pool is a handful of sets, so it isn't estimated first, a full disk still stops it.
+        if self.is_download {
+            self.urgent.lock().unwrap().extend(missing);
+        } else {
+            self.download_v2(missing);
+        }
+    }
+
+    fn slot_status(&self, slot: &ResolvedSlot) -> String {
+        let set_id = match &slot.set_id {
+            Ok(set_id) => *set_id,
+            Err(e) => return format!("Not found: {}", e),
+        };
+        let progress = self
+            .percentage
+            .read()
+            .unwrap()
+            .get(&set_id)
+            .map(|p| *p.read().unwrap());
+        set_status(
+            self.is_ready(set_id),
+            progress,
+            !*self.is_download_finish.read().unwrap(),
+        )
+    }
+
+    // One collection per mod pool, with only the pool's difficulties
+    fn create_pool_collections(&mut self) {
+        let mut requests: Vec<CollectionRequest> = Vec::new();
+        for slot in &self.mappool.slots {
+            let Ok(set_id) = slot.set_id else {
+                continue;
+            };
+            let name = match self.mappool.name.trim() {
+                "" => slot.slot.pool().to_owned(),
+                prefix => format!("{} {}", prefix, slot.slot.pool()),
+            };
+            let index = match requests.iter().position(|r| r.name == name) {
+                Some(index) => index,
+                None => {
+                    requests.push(CollectionRequest {
+                        name,
+                        sets: HashSet::new(),
+                        hashes: Vec::new(),
+                        beatmaps: Some(HashSet::new()),
+                    });
+                    requests.len() - 1
+                }
+            };
+            let request = &mut requests[index];
+            request.sets.insert(set_id);
+            if let Some(beatmaps) = &mut request.beatmaps {
+                beatmaps.insert(slot.slot.beatmap_id);
+            }
+        }
+        if !requests.is_empty() {
+            self.write_collections(requests);
+        }
+    }
+
+    fn import_file(&mut self, path: &Path) {
+        match fs::read_to_string(path) {
+            Ok(text) => self.import(text),
+            Err(e) => {
+                self.list_import.message = Some(format!("can't read '{}': {}", path.display(), e))
+            }
+        }
+    }
+
+    fn finish_imports(&mut self) {
+        let imports: [fn(&mut Self) -> &mut Import; 4] = [
+            |app| &mut app.list_import,
+            |app| &mut app.user_import,
+            |app| &mut app.pack_import,
+            |app| &mut app.collection_import,
+        ];
+        for import in imports {
+            let Some(result) = import(self).result.write().unwrap().take() else {
+                continue;
+            };
+            let message = self.queue_import(result);
+            let import = import(self);
+            import.is_importing = false;
+            import.message = Some(message);
+        }
+    }
+
+    // Queue imported sets that aren't local yet
+    fn queue_import(&mut self, result: ImportResult) -> String {
+        let local_songs = self.local_songs.read().unwrap().clone();
+        let (local, missing): (Vec<u32>, Vec<u32>) = result
+            .sets
+            .into_iter()
+            .partition(|id| local_songs.contains(id));
+        let mut message = format!(
+            "Queued {} sets, {} already local",
+            missing.len(),
+            local.len()
+        );
+        if !result.skipped.is_empty() {
+            message += &format!(", skipped: {}", result.skipped.join("; "));
+        }
+        // Recorded here so only the UI thread writes collections.yaml
+        if let Some(collection) = result.collection {
+            self.saved_collections.record(collection);
+            if let Err(e) = self.saved_collections.save(&self.collections_path) {
+                message += &format!(", can't save the collection: {}", e);
+            }
+        }
+        self.add_song_info(result.songs);
+        self.add_new_songs(missing);
+        message
+    }
+
+    // Sources that only know a set's ID don't replace what an earlier search said about it
+    fn add_song_info(&mut self, songs: Vec<Beatmapset>) {
+        for mut song in songs {
+            if song.title.is_empty() && song.artist.is_empty() {
+                continue;
+            }
+            if let Some(known) = self.song_info.remove(&song.id) {
+                song.merge(known);
+            }
+            self.song_info.insert(song.id, song);
+        }
+    }
+
+    fn export_entry(&self, id: u32) -> ExportEntry {
+        let song = self.song_info.get(&id).cloned().unwrap_or_default();
+        ExportEntry {
+            id,
+            url: set_url(id),
+            artist: song.artist,
+            title: song.title,
+            creator: song.creator,
+            ..Default::default()
+        }
+    }
+
+    fn export_entries(&self) -> Vec<ExportEntry> {
+        let ids = match self.export_scope {
+            ExportScope::Found => &self.new_songs,
+            ExportScope::Selected => &self.selected,
+            ExportScope::Downloaded => return self.history.read().unwrap().clone(),
+        };
+        let mut ids = ids.iter().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.into_iter().map(|id| self.export_entry(id)).collect()
+    }
+
+    // Downloaded archives or folders of `ids`, and set folders already in the songs folder
+    fn set_files(&self, ids: &HashSet<u32>) -> Vec<PathBuf> {
+        let mut files = self
+            .history
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|entry| ids.contains(&entry.id))
+            .filter_map(|entry| entry.file_path.as_ref().map(PathBuf::from))
+            .collect::<Vec<_>>();
+        if let Ok(entries) = fs::read_dir(&self.songs_path) {
+            files.extend(
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.is_dir())
+                    .filter(|path| Self::extract_song_id(path).is_some_and(|id| ids.contains(&id))),
+            );
+        }
+        files
+    }
+
+    // Hashing and writing happen in the background, collection.db is backed up first
+    fn write_collections(&mut self, requests: Vec<CollectionRequest>) {
+        if requests
+            .iter()
+            .any(|request| request.name.trim().is_empty())
+            || self.is_writing_collection.swap(true, Ordering::Relaxed)
+        {
+            return;
+        }
+        let requests = requests
+            .into_iter()
+            .map(|request| {
+                let files = self.set_files(&request.sets);
+                (request, files)
+            })
+            .collect::<Vec<_>>();
+        let path = self.collection_db.clone();
+        let is_writing = self.is_writing_collection.clone();
+        let message = self.collection_message.clone();
+        *message.write().unwrap() = None;
+        thread::spawn(move || {
+            let mut failed = Vec::new();
+            let mut collections = Vec::new();
+            for (mut request, files) in requests {
+                for file in files {
+                    match difficulties(&file) {
+                        Ok(difficulties) => request.hashes.extend(
+                            difficulties
+                                .into_iter()
+                                .filter(|d| match (&request.beatmaps, d.beatmap_id) {
+                                    (Some(beatmaps), Some(id)) => beatmaps.contains(&id),
+                                    (Some(_), None) => false,
+                                    (None, _) => true,
+                                })
+                                .map(|d| d.hash),
+                        ),
+                        Err(e) => failed.push(format!("{}: {}", file.display(), e)),
+                    }
+                }
+                collections.push((request.name, request.hashes));
+            }
+            let names = collections
+                .iter()
+                .map(|(name, _)| format!("'{}'", name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut text = match add_to_collections(&path, &collections) {
+                Ok(added) => format!("Added {} difficulties to {}", added, names),
+                Err(e) => format!("Can't update '{}': {}", path.display(), e),
+            };
+            if !failed.is_empty() {
+                text += &format!(", skipped: {}", failed.join("; "));
+            }
+            *message.write().unwrap() = Some(text);
+            is_writing.store(false, Ordering::Relaxed);
+        });
+    }
+
+    fn export_to_file(&mut self) {
+        let format = self.export_format;
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter(format.extension(), &[format.extension()])
+            .set_file_name(format!("beatmaps.{}", format.extension()))
+            .save_file()
+        else {
+            return;
+        };
+        let entries = self.export_entries();
+        let result = export(&entries, format).and_then(|contents| Ok(fs::write(&path, contents)?));
+        self.export_message = Some(match result {
+            Ok(()) => format!("Exported {} sets to '{}'", entries.len(), path.display()),
+            Err(e) => format!("Export failed: {}", e),
+        });
+    }
+
+    fn retry_search(&mut self) {
+        if let Some(failure) = self.search_failure.take() {
+            if !self.is_fetching {
+                self.is_fetching = true;
+                self.search_position = Some(failure.position.clone());
+                let _ = self.tx_control.send(failure.position);
+            }
+        }
+    }
+
+    fn session_cookie_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let label = ui.label("osu_session cookie: ");
+            ui.add(egui::TextEdit::singleline(&mut self.session_cookie).password(true))
+                .labelled_by(label.id);
+            if ui.button("Save").clicked() {
+                let auth = self.auth.clone();
+                let result = self
+                    .runtime
+                    .block_on(auth.set_session_cookie(&self.session_cookie));
+                self.credentials_message = Some(match result {
+                    Ok(()) => {
+                        self.has_session_cookie = !self.session_cookie.trim().is_empty();
+                        if self.has_session_cookie {
+                            "Session cookie saved".to_owned()
+                        } else {
+                            "Session cookie removed".to_owned()
+                        }
+                    }
+                    Err(e) => format!("Failed to save credentials: {}", e),
+                });
+                self.session_cookie.clear();
+            }
+        });
+        if let Some(message) = &self.credentials_message {
+            ui.label(message);
+        } else if !self.has_session_cookie {
+            ui.label("No session cookie stored, copy osu_session from a logged-in browser");
+        }
+    }
+
+    // Estimate the batch first when that hasn't been done for the sets queued now, the
+    // download starts once it's in
+    fn request_download(&mut self) {
+        if self.unsized_mirrors.contains(&self.selected_server) {
+            log::warn!(
+                "{} doesn't report sizes, not checking the disk space",
+                self.selected_server
+            );
+            self.download_v2(self.queued());
+            return;
+        }
+        // Sets downloaded since don't call for a new estimate
+        let queued = self.queued();
+        let estimated = self.size_estimate.read().unwrap().is_some()
+            && self.estimated.as_ref().is_some_and(|(mirror, ids)| {
+                *mirror == self.selected_server && queued.iter().all(|id| ids.contains(id))
+            });
+        if !estimated {
+            self.download_requested = true;
+            if !self.is_estimating {
+                self.estimate_size();
+            }
+            return;
+        }
+        self.download_requested = false;
+        match self.check_disk_space() {
+            Ok(()) => self.download_v2(self.queued()),
+            Err(e) => self.disk_space_warning = Some(e.to_string()),
+        }
+    }
+
+    fn finish_estimate(&mut self) {
+        if !self.is_estimating || self.size_estimate.read().unwrap().is_none() {
+            return;
+        }
+        self.is_estimating = false;
+        if let (Some(Ok(estimate)), Some((mirror, _))) =
+            (&*self.size_estimate.read().unwrap(), &self.estimated)
+        {
+            if !estimate.reports_size() {
+                self.unsized_mirrors.insert(mirror.clone());
+            }
+        }
+        if self.download_requested {
+            self.request_download();
+        }
+    }
+
+    // Refuse batches the estimate says won't fit, with some headroom. Archives land in the
+    // download folder and extracted sets in the songs folder, which can be another drive.
+    fn check_disk_space(&self) -> Result<()> {
+        let total = match &*self.size_estimate.read().unwrap() {
+            Some(Ok(estimate)) => estimate.total_bytes,
+            Some(Err(e)) => {
+                return Err(eyre!(
+                    "can't check the disk space, the size estimate failed: {}",
+                    e
+                ))
+            }
+            None => {
+                return Err(eyre!(
+                    "can't check the disk space, the size isn't estimated"
+                ))
+            }
+        };
+        let mut dirs = vec![self.download_dir()];
+        if self.extract {
+            dirs.push(&self.songs_path);
+        }
+        for dir in dirs {
+            let available = fs4::available_space(dir)
+                .map_err(|e| eyre!("can't read free space of '{}': {}", dir, e))?;
+            if total + total / 10 > available {
+                return Err(eyre!(
+                    "not enough disk space in '{}', about {} is needed but only {} is free",
+                    dir,
+                    format_size(total),
+                    format_size(available)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn download_v2(&mut self, sets: Vec<u32>) {
         if !self.is_download {
             self.is_download = true;
-            {
-                //
-                let mut is_download_finish = self.is_download_finish.write().unwrap();
-                *is_download_finish = false;
+            self.disk_space_warning = None;
+            *self.download_error.write().unwrap() = None;
+            self.space_saved.store(0, Ordering::Relaxed);
+            *self.is_download_finish.write().unwrap() = false;
+            // Try the selected mirror first, then fall back to the others by priority
+            let mut mirrors = vec![(
+                self.selected_server.clone(),
+                self.server.get(&self.selected_server).unwrap().to_owned(),
+            )];
+            for name in by_priority(&self.server) {
+                if name != self.selected_server {
+                    mirrors.push((name.clone(), self.server[&name].clone()));
+                }
             }
-            let fmt = self.server.get(&self.selected_server).unwrap().to_owned();
+            let mirrors = Arc::new(mirrors);
+            let history = self.history.clone();
+            let context = DownloadContext {
+                limiter: self.limiter.clone(),
+                auth: self.auth.clone(),
+                no_video: self.no_video,
+                extract_to: self.extract.then(|| PathBuf::from(&self.songs_path)),
+                strip: self.strip,
+                space_saved: self.space_saved.clone(),
+                filename_template: self.filename_template.clone(),
+                song_info: Arc::new(self.song_info.clone()),
+            };
+            let downloaded = Arc::new(AtomicUsize::new(0));
+            let failed = Arc::new(AtomicUsize::new(0));
             let (sender, receiver) = channel::bounded::<u32>(5);
             let mut handlers = vec![];
             for _ in 1..=self.number_of_simultaneous_downloads {
                 // Create 5 consumer thread
                 let runtime = self.runtime.clone();
+                let context = context.clone();
                 let receiver = receiver.clone();
-                let fmt = fmt.clone();
+                let urgent = self.urgent.clone();
+                let mirrors = mirrors.clone();
                 let percentage = self.percentage.clone();
-                let songs_path = self.songs_path.clone();
+                let download_path = self.download_dir().to_owned();
+                let download_error = self.download_error.clone();
+                let downloaded = downloaded.clone();
+                let failed = failed.clone();
+                let history = history.clone();
+                let hooks = self.hooks.clone();
                 handlers.push(thread::spawn(move || {
-                    // TODO: Download here
-                    while let Ok(song) = receiver.recv() {
-                        let mut params = HashMap::<String, u32>::new();
-                        params.insert("beatmap_id".to_string(), song);
-                        let url = strfmt(&fmt, &params).unwrap();
-
-                        let progress_rw = percentage.read().unwrap();
-                        let progress = progress_rw.get(&song).unwrap();
-                        let _ = runtime.block_on(download_file(
-                            &url,
-                            &songs_path,
-                            format!("{}.osz", song),
-                            progress.clone(),
-                        ));
+                    loop {
+                        let next = urgent.lock().unwrap().pop_front();
+                        let Some(song) = next.or_else(|| receiver.recv().ok()) else {
+                            break;
+                        };
+                        if download_error.read().unwrap().is_some() {
+                            break;
+                        }
+                        let progress = percentage.write().unwrap().entry(song).or_default().clone();
+                        let mut payload = HookPayload::new(HookEvent::Failure, "failure");
+                        payload.beatmapset_id = Some(song);
+                        for (name, mirror) in mirrors.iter() {
+                            let result = runtime
+                                .block_on(download_file(
+                                    &context,
+                                    mirror,
+                                    song,
+                                    &download_path,
+                                    format!("{}.osz", song),
+                                    progress.clone(),
+                                ))
+                                .and_then(|archive| context.post_process(song, &archive));
+                            payload.mirror = Some(name.clone());
+                            match result {
+                                Ok(path) => {
+                                    payload.event = HookEvent::Success;
+                                    payload.status = "success".to_owned();
+                                    payload.file_path = Some(path.display().to_string());
+                                    break;
+                                }
+                                // Another mirror won't help, stop the batch
+                                Err(e) if is_disk_full(&e) => {
+                                    log::error!("{} failed: {}", song, e);
+                                    *download_error.write().unwrap() =
+                                        Some(format!("Disk full while downloading {}", song));
+                                    payload.error = Some(e.to_string());
+                                    break;
+                                }
+                                Err(e) => {
+                                    log::warn!("{} failed on {}: {}", song, name, e);
+                                    payload.error = Some(e.to_string());
+                                }
+                            }
+                        }
+                        if payload.event == HookEvent::Success {
+                            downloaded.fetch_add(1, Ordering::Relaxed);
+                            let song_info =
+                                context.song_info.get(&song).cloned().unwrap_or_default();
+                            history.write().unwrap().push(ExportEntry {
+                                id: song,
+                                url: set_url(song),
+                                artist: song_info.artist,
+                                title: song_info.title,
+                                creator: song_info.creator,
+                                mirror: payload.mirror.clone(),
+                                file_path: payload.file_path.clone(),
+                            });
+                        } else {
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        // Only fails once the app is shutting down
+                        let _ = hooks.send(payload);
                     }
                 }));
             }
-            let new_songs = self.new_songs.clone();
+            let new_songs = sets;
             let is_download_finish = self.is_download_finish.clone();
-            // TODO: fix should wait until all threads are finish then update is_download_finish to "true"
+            let batch_result = self.batch_result.clone();
+            let hooks = self.hooks.clone();
+            let download_error = self.download_error.clone();
             thread::spawn(move || {
                 // Producer thread
-                for song in new_songs.iter() {
-                    sender.send(*song).unwrap();
+                for song in new_songs {
+                    // Workers are gone once the batch has been stopped
+                    if sender.send(song).is_err() {
+                        break;
+                    }
                 }
                 drop(sender);
                 for handler in handlers {
                     handler.join().unwrap(); // Unwrap the result to handle any potential panics
                 }
+                let stopped = download_error.read().unwrap().clone();
+                let mut payload = HookPayload::new(
+                    HookEvent::BatchFinished,
+                    if stopped.is_some() {
+                        "stopped"
+                    } else {
+                        "finished"
+                    },
+                );
+                *batch_result.write().unwrap() = Some(BatchResult {
+                    failed: failed.load(Ordering::Relaxed),
+                    stopped: stopped.is_some(),
+                });
+                payload.error = stopped;
+                payload.downloaded = Some(downloaded.load(Ordering::Relaxed));
+                payload.failed = Some(failed.load(Ordering::Relaxed));
+                let _ = hooks.send(payload);
                 let mut cur = is_download_finish.write().unwrap();
                 *cur = true;
             });
@@ -243,11 +1537,34 @@ impl eframe::App for BeatmapDownloaderApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("osu! Beatmap Downloader");
+            if !self.profiles.is_empty() {
+                let mut selected = self.profile.clone();
+                ui.add_enabled_ui(*self.is_download_finish.read().unwrap(), |ui| {
+                    egui::ComboBox::from_label("Profile")
+                        .selected_text(self.profile.as_deref().unwrap_or("Shared settings"))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut selected, None, "Shared settings");
+                            for profile in &self.profiles {
+                                ui.selectable_value(&mut selected, Some(profile.clone()), profile);
+                            }
+                        });
+                });
+                // The window reopens with the profile's search client, paths and mirrors
+                if selected != self.profile {
+                    *self.profile_switch.write().unwrap() = Some(selected.unwrap_or_default());
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            }
             ui.horizontal(|ui| {
                 let songs_path_label = ui.label("Songs path: ");
                 ui.text_edit_singleline(&mut self.songs_path)
                     .labelled_by(songs_path_label.id);
             });
+            ui.horizontal(|ui| {
+                let download_path_label = ui.label("Download to (empty for songs path): ");
+                ui.text_edit_singleline(&mut self.download_path)
+                    .labelled_by(download_path_label.id);
+            });
 
             ui.horizontal(|ui| {
                 let simulteneous_downloads = ui.label("Simultaneous downloads: ");
@@ -257,16 +1574,88 @@ impl eframe::App for BeatmapDownloaderApp {
                 .labelled_by(simulteneous_downloads.id);
             });
 
-            let number_of_fetch_songs = *self.number_of_fetch_songs.read().unwrap();
-            let mut number_of_page = number_of_fetch_songs / 50;
-            ui.horizontal(|ui| {
-                let number_of_page_label = ui.label("Number of page");
-                ui.add(egui::DragValue::new(&mut number_of_page))
-                    .labelled_by(number_of_page_label.id);
-                ui.label(format!("{} songs", number_of_fetch_songs));
+            egui::ComboBox::from_label("Fetch")
+                .selected_text(match self.fetch_mode {
+                    FetchMode::Pages => "Number of pages",
+                    FetchMode::SinceDate => "Since date",
+                    FetchMode::SinceLastSync => "Since last sync",
+                    FetchMode::Incremental => "Incremental",
+                    FetchMode::Backfill => "Full backfill",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.fetch_mode, FetchMode::Pages, "Number of pages");
+                    ui.selectable_value(&mut self.fetch_mode, FetchMode::SinceDate, "Since date");
+                    ui.selectable_value(
+                        &mut self.fetch_mode,
+                        FetchMode::SinceLastSync,
+                        "Since last sync",
+                    );
+                    ui.selectable_value(
+                        &mut self.fetch_mode,
+                        FetchMode::Incremental,
+                        "Incremental",
+                    );
+                    ui.selectable_value(&mut self.fetch_mode, FetchMode::Backfill, "Full backfill");
+                });
+            match self.fetch_mode {
+                FetchMode::Pages => {
+                    let number_of_fetch_songs = *self.number_of_fetch_songs.read().unwrap();
+                    let mut number_of_page = number_of_fetch_songs / 50;
+                    ui.horizontal(|ui| {
+                        let number_of_page_label = ui.label("Number of page");
+                        ui.add(egui::DragValue::new(&mut number_of_page))
+                            .labelled_by(number_of_page_label.id);
+                        ui.label(format!("{} songs", number_of_fetch_songs));
+                    });
+                    // Manually round the value to the nearest step of 50
+                    *self.number_of_fetch_songs.write().unwrap() = number_of_page * 50;
+                }
+                FetchMode::SinceDate => {
+                    ui.horizontal(|ui| {
+                        let since_date_label = ui.label("Ranked since (YYYY-MM-DD): ");
+                        ui.text_edit_singleline(&mut self.since_date)
+                            .labelled_by(since_date_label.id);
+                    });
+                }
+                FetchMode::SinceLastSync => (),
+                FetchMode::Incremental => {
+                    let checkpoint = self.sync_state.checkpoints.get(&self.sync_key);
+                    ui.label(match checkpoint {
+                        Some(checkpoint) if checkpoint.pending.is_some() => {
+                            "Continuing an unfinished sync".to_owned()
+                        }
+                        Some(checkpoint) => match (checkpoint.ranked_date, checkpoint.id) {
+                            (Some(ranked_date), _) => format!(
+                                "New maps ranked after {}",
+                                ranked_date.format("%Y-%m-%d %H:%M UTC")
+                            ),
+                            (None, Some(id)) => format!("New maps after set {}", id),
+                            (None, None) => "No checkpoint yet".to_owned(),
+                        },
+                        None => "No checkpoint yet".to_owned(),
+                    });
+                }
+                FetchMode::Backfill => {
+                    let backfill = self.sync_state.backfills.get(&self.sync_key);
+                    ui.label(match backfill {
+                        Some(backfill) if backfill.pending.is_some() => format!(
+                            "Continuing an unfinished backfill: {} missing of {} sets seen",
+                            backfill.found.len(),
+                            backfill.seen
+                        ),
+                        Some(backfill) if backfill.seen > 0 => format!(
+                            "Last backfill: {} missing of {} sets",
+                            backfill.found.len(),
+                            backfill.seen
+                        ),
+                        _ => "Every ranked set, resumable if interrupted".to_owned(),
+                    });
+                }
+            }
+            ui.label(match self.sync_state.last_sync {
+                Some(last_sync) => format!("Last sync: {}", last_sync.format("%Y-%m-%d %H:%M UTC")),
+                None => "Last sync: never".to_owned(),
             });
-            // Manually round the value to the nearest step of 50
-            *self.number_of_fetch_songs.write().unwrap() = number_of_page * 50;
             if ui.button("Reload local songs").clicked() {
                 self.load_songs_from_local();
             }
@@ -275,10 +1664,434 @@ impl eframe::App for BeatmapDownloaderApp {
                 "Number of Local songs '{}'",
                 self.local_songs.read().unwrap().len()
             ));
-            let status = if self.is_fetching { "loading" } else { "idle" };
+            let status = if self.is_fetching {
+                "loading"
+            } else if self.search_failure.is_some() {
+                "failed"
+            } else {
+                "idle"
+            };
             ui.label(format!("Status: {}", status));
-            ui.label(format!("Found {} songs", self.new_songs.len()));
-            let options = self.server.keys().cloned().collect::<Vec<String>>();
+            ui.horizontal(|ui| {
+                ui.label(format!("Found {} songs", self.new_songs.len()));
+                if ui
+                    .add_enabled(
+                        !self.is_fetching && !self.new_songs.is_empty(),
+                        egui::Button::new("Estimate size"),
+                    )
+                    .clicked()
+                {
+                    self.estimate_size();
+                }
+                match &*self.size_estimate.read().unwrap() {
+                    Some(Ok(estimate)) if !estimate.reports_size() => {
+                        ui.label("The mirror doesn't report sizes, free space isn't checked")
+                    }
+                    Some(Ok(estimate)) => ui.label(format!(
+                        "About {} ({} of {} sets sampled)",
+                        format_size(estimate.total_bytes),
+                        estimate.sampled,
+                        estimate.sets
+                    )),
+                    Some(Err(e)) => {
+                        ui.colored_label(egui::Color32::RED, format!("Size estimate failed: {}", e))
+                    }
+                    None => ui.label(""),
+                };
+            });
+            for warning in self.search_warnings.iter() {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!("Partial result: {}", warning),
+                );
+            }
+            let mut retry = false;
+            if let Some(failure) = &self.search_failure {
+                ui.horizontal(|ui| {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!(
+                            "Search stopped at page {}: {}",
+                            failure.position.page + 1,
+                            failure.message
+                        ),
+                    );
+                    retry = ui.button("Retry").clicked();
+                });
+            }
+            if retry {
+                self.retry_search();
+            }
+            if self.is_fetching {
+                // Keep polling for pages while the search is running
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+            egui::CollapsingHeader::new("Import list").show(ui, |ui| {
+                ui.label("osu! links, beatmap or set IDs, or CSV rows, one per line:");
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.import_text)
+                        .desired_rows(4)
+                        .hint_text("https://osu.ppy.sh/beatmapsets/123 or drop a file here"),
+                );
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!self.list_import.is_importing, egui::Button::new("Import"))
+                        .clicked()
+                    {
+                        let text = std::mem::take(&mut self.import_text);
+                        self.import(text);
+                    }
+                    if ui
+                        .add_enabled(
+                            !self.list_import.is_importing,
+                            egui::Button::new("Import file…"),
+                        )
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new().pick_file() {
+                            self.import_file(&path);
+                        }
+                    }
+                    if self.list_import.is_importing {
+                        ui.label("Importing…");
+                    }
+                });
+                if let Some(message) = &self.list_import.message {
+                    ui.label(message);
+                }
+            });
+            egui::CollapsingHeader::new("User maps").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    let user_label = ui.label("Username or ID: ");
+                    ui.text_edit_singleline(&mut self.source_user)
+                        .labelled_by(user_label.id);
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt("user_category")
+                        .selected_text(self.user_category.label())
+                        .show_ui(ui, |ui| {
+                            for category in UserCategory::ALL {
+                                ui.selectable_value(
+                                    &mut self.user_category,
+                                    category,
+                                    category.label(),
+                                );
+                            }
+                        });
+                    if ui
+                        .add_enabled(!self.user_import.is_importing, egui::Button::new("Fetch"))
+                        .clicked()
+                    {
+                        self.fetch_user_maps();
+                    }
+                    if self.user_import.is_importing {
+                        ui.label("Fetching…");
+                    }
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt("score_mode")
+                        .selected_text(format!("{:?}", self.score_mode))
+                        .show_ui(ui, |ui| {
+                            for mode in [
+                                GameMode::Osu,
+                                GameMode::Taiko,
+                                GameMode::Catch,
+                                GameMode::Mania,
+                            ] {
+                                ui.selectable_value(
+                                    &mut self.score_mode,
+                                    mode,
+                                    format!("{:?}", mode),
+                                );
+                            }
+                        });
+                    ui.checkbox(&mut self.scores_best, "Top plays");
+                    ui.checkbox(&mut self.scores_recent, "Recent scores");
+                    if ui
+                        .add_enabled(
+                            !self.user_import.is_importing,
+                            egui::Button::new("Fetch scores"),
+                        )
+                        .clicked()
+                    {
+                        self.fetch_user_scores();
+                    }
+                });
+                if let Some(message) = &self.user_import.message {
+                    ui.label(message);
+                }
+            });
+            self.finish_packs();
+            let mut queue_pack = None;
+            egui::CollapsingHeader::new("Beatmap packs").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    let kind = self.packs.kind;
+                    egui::ComboBox::from_id_salt("pack_type")
+                        .selected_text(self.packs.kind.label())
+                        .show_ui(ui, |ui| {
+                            for kind in PackType::ALL {
+                                ui.selectable_value(&mut self.packs.kind, kind, kind.label());
+                            }
+                        });
+                    if ui.button("Load packs").clicked() || kind != self.packs.kind {
+                        self.load_packs(true);
+                    }
+                    if self.packs.is_loading {
+                        ui.label("Loading…");
+                    }
+                });
+                if let Some(e) = &self.packs.error {
+                    ui.colored_label(egui::Color32::RED, format!("Failed to load packs: {}", e));
+                }
+                egui::ScrollArea::vertical()
+                    .id_salt("packs")
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        for pack in self.packs.packs.iter() {
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_enabled(
+                                        !self.pack_import.is_importing,
+                                        egui::Button::new("Queue missing"),
+                                    )
+                                    .clicked()
+                                {
+                                    queue_pack = Some(pack.clone());
+                                }
+                                ui.label(format!("{} ({})", pack.name, pack.author));
+                            });
+                        }
+                        if self.packs.next.is_some()
+                            && ui
+                                .add_enabled(!self.packs.is_loading, egui::Button::new("More"))
+                                .clicked()
+                        {
+                            self.load_packs(false);
+                        }
+                    });
+                if let Some(message) = &self.pack_import.message {
+                    ui.label(message);
+                }
+            });
+            egui::CollapsingHeader::new("osu!collector").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Collection ID or link");
+                    ui.text_edit_singleline(&mut self.collection_input);
+                    if ui
+                        .add_enabled(
+                            !self.collection_import.is_importing,
+                            egui::Button::new("Queue missing"),
+                        )
+                        .clicked()
+                    {
+                        self.fetch_collection();
+                    }
+                    if self.collection_import.is_importing {
+                        ui.label("Fetching…");
+                    }
+                });
+                if let Some(message) = &self.collection_import.message {
+                    ui.label(message);
+                }
+            });
+            if let Some(pack) = queue_pack {
+                self.queue_pack(pack);
+            }
+            if self.packs.is_loading {
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+            egui::CollapsingHeader::new("Export list").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.export_scope, ExportScope::Found, "Found");
+                    ui.selectable_value(
+                        &mut self.export_scope,
+                        ExportScope::Selected,
+                        format!("Selected ({})", self.selected.len()),
+                    );
+                    ui.selectable_value(
+                        &mut self.export_scope,
+                        ExportScope::Downloaded,
+                        format!("Downloaded ({})", self.history.read().unwrap().len()),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.export_format, ExportFormat::Text, "Links");
+                    ui.selectable_value(&mut self.export_format, ExportFormat::Csv, "CSV");
+                    ui.selectable_value(&mut self.export_format, ExportFormat::Json, "JSON");
+                    if ui.button("Export…").clicked() {
+                        self.export_to_file();
+                    }
+                });
+                if let Some(message) = &self.export_message {
+                    ui.label(message);
+                }
+            });
+            self.finish_mappool();
+            let mut create_pool_collections = false;
+            egui::CollapsingHeader::new("Mappool").show(ui, |ui| {
+                ui.label("Slot and beatmap per row (NM1,123), or YAML like NM: [123, 456]:");
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.mappool.text)
+                        .desired_rows(4)
+                        .hint_text("NM1,https://osu.ppy.sh/b/123"),
+                );
+                ui.horizontal(|ui| {
+                    let is_loading = self.mappool.is_loading;
+                    if ui
+                        .add_enabled(!is_loading, egui::Button::new("Download pool"))
+                        .clicked()
+                    {
+                        let text = self.mappool.text.clone();
+                        self.load_mappool(text);
+                    }
+                    if ui
+                        .add_enabled(!is_loading, egui::Button::new("Import file…"))
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Mappool", &["csv", "tsv", "txt", "yaml", "yml"])
+                            .pick_file()
+                        {
+                            match fs::read_to_string(&path) {
+                                Ok(text) => {
+                                    self.mappool.text = text.clone();
+                                    self.load_mappool(text);
+                                }
+                                Err(e) => {
+                                    self.mappool.error =
+                                        Some(format!("can't read '{}': {}", path.display(), e))
+                                }
+                            }
+                        }
+                    }
+                    if is_loading {
+                        ui.label("Resolving…");
+                        ctx.request_repaint_after(Duration::from_millis(100));
+                    }
+                });
+                if let Some(e) = &self.mappool.error {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+                if !self.mappool.skipped.is_empty() {
+                    ui.label(format!("Skipped: {}", self.mappool.skipped.join("; ")));
+                }
+                if !self.mappool.slots.is_empty() {
+                    let ready = self
+                        .mappool
+                        .slots
+                        .iter()
+                        .filter(|slot| slot.set_id.as_ref().is_ok_and(|id| self.is_ready(*id)))
+                        .count();
+                    ui.label(format!(
+                        "{}/{} slots ready",
+                        ready,
+                        self.mappool.slots.len()
+                    ));
+                    Grid::new("mappool").num_columns(3).show(ui, |ui| {
+                        for slot in &self.mappool.slots {
+                            ui.label(&slot.slot.name);
+                            ui.add(Hyperlink::from_label_and_url(
+                                slot.slot.beatmap_id.to_string(),
+                                format!("https://osu.ppy.sh/b/{}", slot.slot.beatmap_id),
+                            ));
+                            ui.label(self.slot_status(slot));
+                            ui.end_row();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Collection prefix");
+                        ui.text_edit_singleline(&mut self.mappool.name);
+                        create_pool_collections = ui
+                            .add_enabled(
+                                !self.is_writing_collection.load(Ordering::Relaxed),
+                                egui::Button::new("Create a collection per pool"),
+                            )
+                            .clicked();
+                    });
+                    if let Some(message) = &*self.collection_message.read().unwrap() {
+                        ui.label(message);
+                    }
+                }
+            });
+            if create_pool_collections {
+                self.create_pool_collections();
+            }
+            let mut write_collection = None;
+            egui::CollapsingHeader::new("osu! collection").show(ui, |ui| {
+                ui.label(format!("Writes to '{}'", self.collection_db.display()));
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    "Close osu! first, it overwrites collection.db when it exits",
+                );
+                let is_writing = self.is_writing_collection.load(Ordering::Relaxed);
+                ui.horizontal(|ui| {
+                    ui.label("Collection");
+                    ui.text_edit_singleline(&mut self.collection_name);
+                    if ui
+                        .add_enabled(!is_writing, egui::Button::new("Add downloaded maps"))
+                        .clicked()
+                    {
+                        write_collection = Some(CollectionRequest {
+                            name: self.collection_name.clone(),
+                            sets: self.history.read().unwrap().iter().map(|e| e.id).collect(),
+                            hashes: Vec::new(),
+                            beatmaps: None,
+                        });
+                    }
+                });
+                for collection in &self.saved_collections.collections {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(!is_writing, egui::Button::new("Add"))
+                            .clicked()
+                        {
+                            write_collection = Some(CollectionRequest {
+                                name: collection.name.clone(),
+                                sets: collection.beatmapsets.iter().copied().collect(),
+                                hashes: collection.checksums.clone(),
+                                beatmaps: None,
+                            });
+                        }
+                        ui.label(format!(
+                            "{} ({} sets)",
+                            collection.name,
+                            collection.beatmapsets.len()
+                        ));
+                    });
+                }
+                if is_writing {
+                    ui.label("Writing…");
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                }
+                if let Some(message) = &*self.collection_message.read().unwrap() {
+                    ui.label(message);
+                }
+            });
+            if let Some(request) = write_collection {
+                self.write_collections(vec![request]);
+            }
+            let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+            if let Some(path) = dropped.into_iter().find_map(|file| file.path) {
+                self.import_file(&path);
+            }
+            self.finish_imports();
+            self.finish_batch();
+            self.finish_estimate();
+            if self.is_estimating {
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+            if [
+                &self.list_import,
+                &self.user_import,
+                &self.pack_import,
+                &self.collection_import,
+            ]
+            .iter()
+            .any(|import| import.is_importing)
+            {
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+            let options = by_priority(&self.server);
             egui::ComboBox::from_label("Select an Option")
                 .selected_text(self.selected_server.clone())
                 .show_ui(ui, |ui| {
@@ -287,72 +2100,258 @@ impl eframe::App for BeatmapDownloaderApp {
                     }
                 });
             ui.label(format!("You selected: {}", self.selected_server));
+            let needs_session = self
+                .server
+                .get(&self.selected_server)
+                .is_some_and(|mirror| mirror.auth == Some(AuthKind::Session));
+            if needs_session {
+                self.session_cookie_ui(ui);
+            }
+            let supports_no_video = self
+                .server
+                .get(&self.selected_server)
+                .is_some_and(|mirror| mirror.supports_no_video());
+            ui.add_enabled(
+                supports_no_video,
+                egui::Checkbox::new(&mut self.no_video, "Download without video"),
+            );
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.strip.video, "Strip video");
+                ui.checkbox(&mut self.strip.storyboard, "Strip storyboard");
+                let space_saved = self.space_saved.load(Ordering::Relaxed);
+                if space_saved > 0 {
+                    ui.label(format!("Saved {}", format_size(space_saved)));
+                }
+            });
+            ui.checkbox(&mut self.extract, "Extract into the songs folder");
 
             // Create a column layout with 2 columns
             ui.columns(10, |columns| {
                 if columns[0].button("Find new beatmaps").clicked() {
-                    self.is_download = false;
-                    self.percentage =
-                        Arc::new(RwLock::new(HashMap::<u32, Arc<RwLock<f32>>>::new()));
                     self.find_new_songs()
                 }
                 // First column
                 if columns[1].button("Download").clicked() {
-                    self.download_v2();
+                    self.request_download();
                 }
-                let result = if *self.is_download_finish.read().unwrap() {
+                let result = if self.download_requested {
+                    "Estimating size..."
+                } else if *self.is_download_finish.read().unwrap() {
                     "Finish"
                 } else {
                     "Downloading..."
                 };
                 columns[2].label(result);
             });
+            let mut download_anyway = false;
+            if let Some(warning) = &self.disk_space_warning {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::RED, warning);
+                    download_anyway = ui.button("Download anyway").clicked();
+                });
+            }
+            if download_anyway {
+                self.download_v2(self.queued());
+            }
+            if let Some(e) = &*self.download_error.read().unwrap() {
+                ui.colored_label(egui::Color32::RED, e);
+            }
 
             self.list_new_songs(ui);
         });
     }
+
+    // Progress of a running search since its last write
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if self.is_fetching {
+            self.save_sync_state();
+        }
+        // Downloads of a batch that's still running
+        if self.is_download {
+            self.save_history();
+        }
+    }
+}
+
+fn queue(found: &HashSet<u32>, local_songs: &HashSet<u32>, downloaded: &HashSet<u32>) -> Vec<u32> {
+    let mut queued = found
+        .iter()
+        .copied()
+        .filter(|id| !local_songs.contains(id) && !downloaded.contains(id))
+        .collect::<Vec<_>>();
+    queued.sort_unstable();
+    queued
+}
+
+// Sets of a mappool that aren't in the library or downloaded yet
+fn pool_missing(slots: &[ResolvedSlot], is_ready: impl Fn(u32) -> bool) -> Vec<u32> {
+    let mut missing = slots
+        .iter()
+        .filter_map(|slot| slot.set_id.clone().ok())
+        .filter(|id| !is_ready(*id))
+        .collect::<Vec<_>>();
+    missing.sort_unstable();
+    missing.dedup();
+    missing
+}
+
+// Status of a mappool set, `progress` is `None` until the set has been queued
+fn set_status(is_ready: bool, progress: Option<f32>, is_downloading: bool) -> String {
+    if is_ready {
+        return "Ready".to_owned();
+    }
+    match progress {
+        Some(p) if p > 0.0 => format!("Downloading {:.0}%", p * 100.0),
+        Some(_) if is_downloading => "Queued".to_owned(),
+        _ => "Missing".to_owned(),
+    }
+}
+
+// The search client, with the mode and status when they aren't the default ranked osu!standard
+fn sync_key(search_client: &str, mode: Mode, status: Status) -> String {
+    if mode == Mode::default() && status == Status::default() {
+        search_client.to_owned()
+    } else {
+        format!("{} {} {}", search_client, mode.name(), status.name())
+    }
+}
+
+// Shared by every download worker
+#[derive(Clone)]
+struct DownloadContext {
+    limiter: Arc<RateLimiter>,
+    auth: Arc<OsuAuth>,
+    no_video: bool,
+    filename_template: Option<String>,
+    song_info: Arc<HashMap<u32, Beatmapset>>,
+    extract_to: Option<PathBuf>,
+    strip: StripOptions,
+    space_saved: Arc<AtomicU64>,
+}
+
+impl DownloadContext {
+    // A broken archive fails like a broken download, so the next mirror is tried.
+    // Returns where the set ended up.
+    fn post_process(&self, beatmap_id: u32, archive: &Path) -> Result<PathBuf> {
+        if self.strip.any() {
+            let saved = strip_osz(archive, self.strip)?;
+            if saved > 0 {
+                log::info!("stripped {} from {}", format_size(saved), beatmap_id);
+                self.space_saved.fetch_add(saved, Ordering::Relaxed);
+            }
+        }
+        match &self.extract_to {
+            Some(songs_path) => extract_osz(
+                archive,
+                songs_path,
+                beatmap_id,
+                self.song_info.get(&beatmap_id),
+            ),
+            None => Ok(archive.to_owned()),
+        }
+    }
+
+    // Name from `filename_template`, unless the title or artist is missing
+    fn template_file_name(&self, song: &Beatmapset) -> Option<String> {
+        let template = self.filename_template.as_ref()?;
+        if song.title.is_empty() || song.artist.is_empty() {
+            return None;
+        }
+        let vars = HashMap::from([
+            ("id".to_owned(), song.id.to_string()),
+            ("title".to_owned(), song.title.clone()),
+            ("artist".to_owned(), song.artist.clone()),
+            ("creator".to_owned(), song.creator.clone()),
+        ]);
+        match strfmt(template, &vars) {
+            Ok(name) => sanitize_filename(&name),
+            Err(e) => {
+                log::warn!("invalid filename template '{}': {}", template, e);
+                None
+            }
+        }
+    }
 }
 
 async fn download_file(
-    url: &str,
+    context: &DownloadContext,
+    mirror: &Mirror,
+    beatmap_id: u32,
     file_path: &String,
     default_file_name: String,
     progress: Arc<RwLock<f32>>,
-) -> Result<()> {
+) -> Result<PathBuf> {
     // Send a GET request to the URL
     let client = reqwest::Client::new();
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::USER_AGENT,
-        reqwest::header::HeaderValue::from_static("Mozilla/5.0"),
-    );
-    headers.insert(
-        reqwest::header::ACCEPT,
-        reqwest::header::HeaderValue::from_static("application/json"),
-    );
-    let mut response = client.get(url).headers(headers).send().await?;
-
-    let mut file_name = default_file_name; // set default file name to song
-    if let Some(content_disposition) = response.headers().get(CONTENT_DISPOSITION) {
-        // Parse the header to extract the filename
-        if let Ok(content_disposition_str) = content_disposition.to_str() {
-            if let Some(name) = content_disposition_str.split("filename=").nth(1) {
-                let name = name.trim_matches('"');
-                file_name = urlencoding::decode(name)?.to_string();
-            }
-        }
+    let request = mirror
+        .request(
+            &client,
+            Method::GET,
+            &context.auth,
+            beatmap_id,
+            context.no_video,
+        )
+        .await?;
+    let mut response = context.limiter.send(request).await?;
+    if !response.status().is_success() {
+        return Err(eyre!("Request failed with status: {}", response.status()));
     }
-    // Open a file to write the content to
-    let total_size = response.content_length().unwrap_or(0);
-    let mut downloaded = 0u64;
 
+    // Otherwise mirrors choose the name, but it mustn't leave `file_path` or be invalid on disk
+    let templated = context
+        .song_info
+        .get(&beatmap_id)
+        .and_then(|song| context.template_file_name(song));
+    let file_name = templated
+        .clone()
+        .or_else(|| {
+            response
+                .headers()
+                .get(CONTENT_DISPOSITION)
+                .and_then(|header| header.to_str().ok())
+                .and_then(content_disposition_filename)
+                .and_then(|name| sanitize_filename(&name))
+        })
+        .unwrap_or(default_file_name);
+    // Open a file to write the content to
     let file_path = Path::new(&file_path);
     let tmp_file_name = file_name.clone() + ".download";
-    let dest_path = file_path.join(file_name);
+    let mut dest_path = file_path.join(file_name);
     let tmp_dest_path = file_path.join(tmp_file_name);
     // Write the content to the file in chunks
-    let mut dest_file = std::fs::File::create(&tmp_dest_path).unwrap();
+    let mut dest_file = fs::File::create(&tmp_dest_path)?;
+    if let Err(e) = write_response(&mut response, &mut dest_file, &progress).await {
+        // Don't leave a partial file behind, especially on a full disk
+        drop(dest_file);
+        let _ = fs::remove_file(&tmp_dest_path);
+        return Err(e);
+    }
+    // Sets queued without metadata, like imported IDs, are named from their own difficulties
+    if context.filename_template.is_some() && templated.is_none() {
+        match osz_metadata(&tmp_dest_path, beatmap_id) {
+            Ok(song) => {
+                if let Some(name) = context.template_file_name(&song) {
+                    dest_path = file_path.join(name);
+                }
+            }
+            Err(e) => log::warn!(
+                "no metadata for {}, filename_template not used: {}",
+                beatmap_id,
+                e
+            ),
+        }
+    }
+    fs::rename(tmp_dest_path, &dest_path)?;
+    Ok(dest_path)
+}
 
+async fn write_response(
+    response: &mut Response,
+    dest_file: &mut fs::File,
+    progress: &RwLock<f32>,
+) -> Result<()> {
+    let total_size = response.content_length().unwrap_or(0);
+    let mut downloaded = 0u64;
     while let Some(chunk) = response.chunk().await? {
         downloaded += chunk.len() as u64;
 
@@ -366,8 +2365,141 @@ async fn download_file(
         *progress = current;
 
         // Write the chunk to the file
-        dest_file.write_all(&chunk).unwrap();
+        dest_file.write_all(&chunk)?;
     }
-    fs::rename(tmp_dest_path, dest_path)?;
     Ok(())
 }
+
+fn is_disk_full(e: &eyre::Report) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| matches!(e.kind(), ErrorKind::StorageFull | ErrorKind::QuotaExceeded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use std::io::Cursor;
+    use zip::write::SimpleFileOptions;
+
+    fn context(filename_template: Option<&str>) -> DownloadContext {
+        let limiter = Arc::new(RateLimiter::default());
+        DownloadContext {
+            auth: Arc::new(OsuAuth::new(
+                0,
+                String::new(),
+                "unused".into(),
+                limiter.clone(),
+            )),
+            limiter,
+            no_video: false,
+            filename_template: filename_template.map(str::to_owned),
+            song_info: Default::default(),
+            extract_to: None,
+            strip: StripOptions::default(),
+            space_saved: Default::default(),
+        }
+    }
+
+    async fn download(context: &DownloadContext, base_url: &str, dir: &Path) -> Result<PathBuf> {
+        let mirror = Mirror::new(&format!("{}/d/{{beatmap_id}}", base_url));
+        download_file(
+            context,
+            &mirror,
+            7,
+            &dir.display().to_string(),
+            "7.osz".to_owned(),
+            Default::default(),
+        )
+        .await
+    }
+
+    #[test]
+    fn test_queue() {
+        let found = HashSet::from([1, 2, 3, 4, 5]);
+        let local_songs = HashSet::from([2]);
+        let downloaded = HashSet::from([3]);
+        assert_eq!(queue(&found, &local_songs, &downloaded), vec![1, 4, 5]);
+    }
+
+    #[test]
+    fn test_pool_readiness() {
+        let slot = |name: &str, set_id| ResolvedSlot {
+            slot: crate::mappool::Slot {
+                name: name.to_owned(),
+                beatmap_id: 1,
+            },
+            set_id,
+        };
+        let slots = vec![
+            slot("NM1", Ok(3)),
+            slot("NM2", Ok(1)),
+            slot("HD1", Ok(3)),
+            slot("HR1", Err("not found".to_owned())),
+            slot("DT1", Ok(2)),
+        ];
+        // Set 2 is already downloaded
+        assert_eq!(pool_missing(&slots, |id| id == 2), vec![1, 3]);
+
+        assert_eq!(set_status(true, None, false), "Ready");
+        assert_eq!(set_status(false, Some(0.5), true), "Downloading 50%");
+        assert_eq!(set_status(false, Some(0.0), true), "Queued");
+        // Left over from a batch that's over
+        assert_eq!(set_status(false, Some(0.0), false), "Missing");
+        assert_eq!(set_status(false, None, true), "Missing");
+    }
+
+    #[test]
+    fn test_is_disk_full() {
+        let full = eyre::Report::new(std::io::Error::from(ErrorKind::StorageFull));
+        assert!(is_disk_full(&full));
+        let quota = eyre::Report::new(std::io::Error::from(ErrorKind::QuotaExceeded));
+        assert!(is_disk_full(&quota));
+        let denied = eyre::Report::new(std::io::Error::from(ErrorKind::PermissionDenied));
+        assert!(!is_disk_full(&denied));
+        assert!(!is_disk_full(&eyre!("no space left")));
+    }
+
+    #[tokio::test]
+    async fn test_partial_download_removed() {
+        // Promises more than it sends
+        let response = b"HTTP/1.1 200 OK\r\ncontent-length: 1000\r\n\r\npartial".to_vec();
+        let base_url = test_server::serve_raw(vec![("/d/7", response)]).await;
+        let dir = std::env::temp_dir().join(format!("partial_download_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(download(&context(None), &base_url, &dir).await.is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_template_from_archive() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("a.osu", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"[Metadata]\nTitle:Song\nArtist:Band\nCreator:Mapper\n")
+            .unwrap();
+        let osz = zip.finish().unwrap().into_inner();
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            osz.len()
+        )
+        .into_bytes();
+        response.extend(osz);
+        let base_url = test_server::serve_raw(vec![("/d/7", response)]).await;
+        let dir = std::env::temp_dir().join(format!("template_download_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Only the ID of set 7 is known before it's downloaded
+        let mut context = context(Some("{id} {artist} - {title} ({creator}).osz"));
+        context.song_info = Arc::new(HashMap::from([(
+            7,
+            Beatmapset {
+                id: 7,
+                ..Default::default()
+            },
+        )]));
+        let path = download(&context, &base_url, &dir).await.unwrap();
+        assert_eq!(path, dir.join("7 Band - Song (Mapper).osz"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
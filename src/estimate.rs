@@ -0,0 +1,143 @@
+use crate::auth::OsuAuth;
+use crate::mirror::Mirror;
+use crate::ratelimit::RateLimiter;
+use eyre::{eyre, Result};
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Method, Response};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SizeEstimate {
+    pub sets: usize,
+    pub sampled: usize,
+    pub total_bytes: u64,
+}
+
+impl SizeEstimate {
+    // Nothing was sampled when the mirror answers without a Content-Length
+    pub fn reports_size(&self) -> bool {
+        self.sets == 0 || self.sampled > 0
+    }
+}
+
+fn content_length(response: &Response) -> Option<u64> {
+    // `Response::content_length` is always 0 for HEAD, read the header instead
+    response
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+// Size of one set on `mirror`, from a HEAD request or the headers of a GET.
+// `None` when the mirror has the set but doesn't say how big it is.
+pub async fn set_size(
+    limiter: &RateLimiter,
+    auth: &OsuAuth,
+    mirror: &Mirror,
+    beatmap_id: u32,
+    no_video: bool,
+) -> Result<Option<u64>> {
+    let client = reqwest::Client::new();
+    let mut found = false;
+    for method in [Method::HEAD, Method::GET] {
+        let request = mirror
+            .request(&client, method, auth, beatmap_id, no_video)
+            .await?;
+        let response = limiter.send(request).await?;
+        if response.status().is_success() {
+            if let Some(size) = content_length(&response) {
+                return Ok(Some(size));
+            }
+            found = true;
+        }
+        // Dropping the GET response closes it before the body is read
+    }
+    if found {
+        return Ok(None);
+    }
+    Err(eyre!("{} isn't on the mirror", beatmap_id))
+}
+
+// Extrapolate the size of `ids` from up to `samples` evenly spread sets
+pub async fn estimate_size(
+    limiter: &RateLimiter,
+    auth: &OsuAuth,
+    mirror: &Mirror,
+    no_video: bool,
+    ids: &[u32],
+    samples: usize,
+) -> Result<SizeEstimate> {
+    if ids.is_empty() {
+        return Ok(SizeEstimate {
+            sets: 0,
+            sampled: 0,
+            total_bytes: 0,
+        });
+    }
+    let step = ids.len().div_ceil(samples.max(1));
+    let mut sampled = 0;
+    let mut sampled_bytes = 0;
+    let mut no_size = false;
+    for id in ids.iter().step_by(step) {
+        match set_size(limiter, auth, mirror, *id, no_video).await {
+            Ok(Some(size)) => {
+                sampled += 1;
+                sampled_bytes += size;
+            }
+            Ok(None) => no_size = true,
+            Err(e) => log::warn!("failed to get size of {}: {}", id, e),
+        }
+    }
+    if sampled == 0 && no_size {
+        return Ok(SizeEstimate {
+            sets: ids.len(),
+            sampled: 0,
+            total_bytes: 0,
+        });
+    }
+    if sampled == 0 {
+        return Err(eyre!("the mirror didn't report any set size"));
+    }
+    Ok(SizeEstimate {
+        sets: ids.len(),
+        sampled,
+        total_bytes: sampled_bytes / sampled as u64 * ids.len() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_estimate_size() {
+        let base_url = test_server::serve(vec![("/d/1", "1234"), ("/d/3", "12345678")]).await;
+        let limiter = Arc::new(RateLimiter::default());
+        let auth = OsuAuth::new(0, String::new(), "unused".into(), limiter.clone());
+        let mirror = Mirror::new(&format!("{}/d/{{beatmap_id}}", base_url));
+
+        assert_eq!(
+            set_size(&limiter, &auth, &mirror, 1, false).await.unwrap(),
+            Some(4)
+        );
+        // Every other set is sampled
+        let estimate = estimate_size(&limiter, &auth, &mirror, false, &[1, 2, 3, 4], 2)
+            .await
+            .unwrap();
+        assert_eq!((estimate.sets, estimate.sampled), (4, 2));
+        assert_eq!(estimate.total_bytes, 24);
+        // Set 2 is missing and left out of the average
+        let estimate = estimate_size(&limiter, &auth, &mirror, false, &[1, 2, 3], 3)
+            .await
+            .unwrap();
+        assert_eq!((estimate.sampled, estimate.total_bytes), (2, 18));
+
+        assert!(estimate_size(&limiter, &auth, &mirror, false, &[2], 1)
+            .await
+            .is_err());
+    }
+}
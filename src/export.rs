@@ -0,0 +1,139 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Maps downloaded in earlier sessions, for the "Downloaded" export and collections
+pub const HISTORY_PATH: &str = "history.yaml";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    // One link per line
+    Text,
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Text => "txt",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct ExportEntry {
+    pub id: u32,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub artist: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub creator: String,
+    // Set for downloaded maps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
+}
+
+pub fn load_history(path: &Path) -> Result<Vec<ExportEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = std::fs::File::open(path)?;
+    Ok(serde_yaml::from_reader(file)?)
+}
+
+pub fn save_history(path: &Path, history: &[ExportEntry]) -> Result<()> {
+    let file = std::fs::File::create(path)?;
+    serde_yaml::to_writer(file, history)?;
+    Ok(())
+}
+
+pub fn set_url(id: u32) -> String {
+    format!("https://osu.ppy.sh/beatmapsets/{}", id)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+// Every format can be read back by the list import
+pub fn export(entries: &[ExportEntry], format: ExportFormat) -> Result<String> {
+    Ok(match format {
+        ExportFormat::Text => entries
+            .iter()
+            .map(|entry| entry.url.clone() + "\n")
+            .collect(),
+        ExportFormat::Csv => {
+            let mut csv = "id,url,artist,title,creator,mirror,file_path\n".to_owned();
+            for entry in entries {
+                let fields = [
+                    entry.id.to_string(),
+                    entry.url.clone(),
+                    entry.artist.clone(),
+                    entry.title.clone(),
+                    entry.creator.clone(),
+                    entry.mirror.clone().unwrap_or_default(),
+                    entry.file_path.clone().unwrap_or_default(),
+                ];
+                let fields = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+                csv += &(fields.join(",") + "\n");
+            }
+            csv
+        }
+        ExportFormat::Json => serde_json::to_string_pretty(entries)? + "\n",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{parse_list, ImportEntry};
+
+    #[test]
+    fn test_export_roundtrip() {
+        let entries = vec![
+            ExportEntry {
+                id: 1,
+                url: set_url(1),
+                artist: "Artist".to_owned(),
+                title: "Hello, \"World\"".to_owned(),
+                ..Default::default()
+            },
+            ExportEntry {
+                id: 2,
+                url: set_url(2),
+                title: "1973".to_owned(),
+                mirror: Some("nerinyan".to_owned()),
+                file_path: Some("Songs/2.osz".to_owned()),
+                ..Default::default()
+            },
+        ];
+        let csv = export(&entries, ExportFormat::Csv).unwrap();
+        assert!(csv.contains(r#"1,https://osu.ppy.sh/beatmapsets/1,Artist,"Hello, ""World""",,,"#));
+        let json = export(&entries, ExportFormat::Json).unwrap();
+        let parsed: Vec<ExportEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, entries);
+
+        for format in [ExportFormat::Text, ExportFormat::Csv, ExportFormat::Json] {
+            let (imported, skipped) = parse_list(&export(&entries, format).unwrap());
+            assert_eq!(imported, vec![ImportEntry::Set(1), ImportEntry::Set(2)]);
+            assert!(skipped.is_empty());
+        }
+
+        let path = std::env::temp_dir().join(format!("history_test_{}.yaml", std::process::id()));
+        save_history(&path, &entries).unwrap();
+        assert_eq!(load_history(&path).unwrap(), entries);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use crate::client::Beatmapset;
+use crate::filename::sanitize_filename;
+use eyre::{eyre, Result};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+fn osu_metadata(contents: &str, key: &str) -> Option<String> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+        .map(|value| value.trim().to_owned())
+}
+
+// Artist, title and creator from the first difficulty, for sets the search didn't describe
+fn archive_metadata<R: io::Read + io::Seek>(
+    archive: &mut ZipArchive<R>,
+    beatmap_id: u32,
+) -> Result<Beatmapset> {
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if !file.name()?.to_ascii_lowercase().ends_with(".osu") {
+            continue;
+        }
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        return Ok(Beatmapset {
+            id: beatmap_id,
+            title: osu_metadata(&contents, "Title").unwrap_or_default(),
+            artist: osu_metadata(&contents, "Artist").unwrap_or_default(),
+            creator: osu_metadata(&contents, "Creator").unwrap_or_default(),
+            ranked_date: None,
+        });
+    }
+    Err(eyre!("archive has no .osu file"))
+}
+
+pub fn osz_metadata(archive_path: &Path, beatmap_id: u32) -> Result<Beatmapset> {
+    let mut archive = ZipArchive::new(fs::File::open(archive_path)?)
+        .map_err(|e| eyre!("{} is not a valid .osz: {}", archive_path.display(), e))?;
+    archive_metadata(&mut archive, beatmap_id)
+}
+
+// Unpack a downloaded .osz into a "{id} {artist} - {title}" folder of `songs_path`,
+// the name `extract_song_id` expects, and remove the archive
+pub fn extract_osz(
+    archive_path: &Path,
+    songs_path: &Path,
+    beatmap_id: u32,
+    song: Option<&Beatmapset>,
+) -> Result<PathBuf> {
+    let mut archive = ZipArchive::new(fs::File::open(archive_path)?)
+        .map_err(|e| eyre!("{} is not a valid .osz: {}", archive_path.display(), e))?;
+    // Also checks the archive is a beatmap set before anything is written
+    let metadata = archive_metadata(&mut archive, beatmap_id)?;
+    let (artist, title) = match song {
+        Some(song) if !song.title.is_empty() => (song.artist.clone(), song.title.clone()),
+        _ => (metadata.artist, metadata.title),
+    };
+    let folder = sanitize_filename(&format!("{} {} - {}", beatmap_id, artist, title))
+        .unwrap_or_else(|| beatmap_id.to_string());
+    let dest = songs_path.join(folder);
+    let existed = dest.exists();
+    if let Err(e) = unpack(&mut archive, &dest) {
+        // Only clean up a folder this call created
+        if !existed {
+            let _ = fs::remove_dir_all(&dest);
+        }
+        return Err(e);
+    }
+    fs::remove_file(archive_path)?;
+    Ok(dest)
+}
+
+fn unpack<R: io::Read + io::Seek>(archive: &mut ZipArchive<R>, dest: &Path) -> Result<()> {
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        // Entries like "../x" would escape the folder
+        let Some(name) = file.enclosed_name() else {
+            return Err(eyre!("unsafe path in archive: {}", file.name()?));
+        };
+        let path = dest.join(name);
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut file, &mut fs::File::create(&path)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn write_osz(path: &Path, files: &[(&str, &str)]) {
+        let mut writer = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, contents) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_extract_osz() {
+        let songs_path = std::env::temp_dir().join("downloader_test_extract");
+        let _ = fs::remove_dir_all(&songs_path);
+        fs::create_dir_all(&songs_path).unwrap();
+
+        let archive = songs_path.join("1.osz");
+        write_osz(
+            &archive,
+            &[
+                ("audio.mp3", ""),
+                (
+                    "a.osu",
+                    "osu file format v14\n[Metadata]\nTitle:Song\nArtist:Artist\n",
+                ),
+            ],
+        );
+        let dest = extract_osz(&archive, &songs_path, 1, None).unwrap();
+        assert_eq!(dest, songs_path.join("1 Artist - Song"));
+        assert!(dest.join("a.osu").exists());
+        assert!(!archive.exists());
+
+        let archive = songs_path.join("2.osz");
+        write_osz(&archive, &[("../evil.osu", "")]);
+        assert!(extract_osz(&archive, &songs_path, 2, None).is_err());
+        assert!(!songs_path.join("evil.osu").exists());
+        assert!(archive.exists());
+
+        fs::write(&archive, "not a zip").unwrap();
+        assert!(extract_osz(&archive, &songs_path, 2, None).is_err());
+        fs::remove_dir_all(&songs_path).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+// Characters Windows refuses in file names, on top of path separators
+const FORBIDDEN: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+const MAX_LEN: usize = 200;
+
+// Split header parameters on `;`, ignoring the ones inside quoted strings
+fn split_params(header: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in header.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    params.push(&header[start..]);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .map(|v| v.strip_suffix('"').unwrap_or(v))
+    else {
+        return value.to_owned();
+    };
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+// RFC 5987 `charset'language'percent-encoded`
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let bytes = urlencoding::decode_binary(parts.next()?.as_bytes());
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes.into_owned()).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.iter().map(|b| *b as char).collect())
+    } else {
+        None
+    }
+}
+
+// File name from a Content-Disposition header, `filename*` winning over `filename`
+pub fn content_disposition_filename(header: &str) -> Option<String> {
+    let mut filename = None;
+    for param in split_params(header).into_iter().skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        match key.as_str() {
+            "filename*" => {
+                if let Some(name) = decode_ext_value(&unquote(value)) {
+                    return Some(name);
+                }
+            }
+            "filename" => {
+                let name = unquote(value);
+                // Some mirrors percent-encode the plain parameter too
+                filename = Some(match urlencoding::decode(&name) {
+                    Ok(decoded) => decoded.into_owned(),
+                    Err(_) => name,
+                });
+            }
+            _ => (),
+        }
+    }
+    filename
+}
+
+// A name that stays inside the download directory and is valid on every platform
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || FORBIDDEN.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    // Windows drops trailing dots and spaces
+    let name = name.trim().trim_end_matches(['.', ' ']);
+    if name.is_empty() || name.chars().all(|c| c == '.' || c == '_') {
+        return None;
+    }
+    let mut name = truncate(name, MAX_LEN);
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        name.insert(0, '_');
+    }
+    Some(name)
+}
+
+// Shorten to `max` bytes on a char boundary, keeping the extension
+fn truncate(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_owned();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if name.len() - i <= 16 => name.split_at(i),
+        _ => (name, ""),
+    };
+    let mut end = max - ext.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end(), ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_disposition_filename() {
+        let name = |header| content_disposition_filename(header);
+        assert_eq!(
+            name(r#"attachment; filename="1 Artist - Title.osz""#).as_deref(),
+            Some("1 Artist - Title.osz")
+        );
+        assert_eq!(
+            name("attachment; filename=1%20a.osz; size=10").as_deref(),
+            Some("1 a.osz")
+        );
+        assert_eq!(
+            name(r#"attachment; filename="a; \"b\".osz"; size=10"#).as_deref(),
+            Some(r#"a; "b".osz"#)
+        );
+        assert_eq!(
+            name("attachment; filename=\"fallback.osz\"; filename*=UTF-8''%E3%81%82.osz")
+                .as_deref(),
+            Some("あ.osz")
+        );
+        assert_eq!(
+            name("attachment; FILENAME*=iso-8859-1'en'caf%E9.osz").as_deref(),
+            Some("café.osz")
+        );
+        assert_eq!(name("attachment"), None);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(
+            sanitize_filename("../../etc/passwd").as_deref(),
+            Some(".._.._etc_passwd")
+        );
+        assert_eq!(
+            sanitize_filename("C:\\a<b>|c?.osz").as_deref(),
+            Some("C__a_b__c_.osz")
+        );
+        assert_eq!(sanitize_filename("con.osz").as_deref(), Some("_con.osz"));
+        assert_eq!(sanitize_filename("name. . ").as_deref(), Some("name"));
+        assert_eq!(sanitize_filename(".."), None);
+        assert_eq!(sanitize_filename("/"), None);
+        let long = format!("{}.osz", "あ".repeat(100));
+        let short = sanitize_filename(&long).unwrap();
+        assert!(short.len() <= MAX_LEN && short.ends_with(".osz"));
+    }
+}
@@ -0,0 +1,154 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::runtime::Runtime;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Success,
+    Failure,
+    BatchFinished,
+}
+
+// Command run on `on`, given the details as OSU_* environment variables and JSON on stdin
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Hook {
+    pub on: HookEvent,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HookPayload {
+    pub event: HookEvent,
+    // "success" or "failure" for a set, "finished" or "stopped" for a batch
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub beatmapset_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloaded: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed: Option<usize>,
+}
+
+impl HookPayload {
+    pub fn new(event: HookEvent, status: &str) -> Self {
+        Self {
+            event,
+            status: status.to_owned(),
+            beatmapset_id: None,
+            file_path: None,
+            mirror: None,
+            error: None,
+            downloaded: None,
+            failed: None,
+        }
+    }
+
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![("OSU_STATUS", self.status.clone())];
+        let optional = [
+            (
+                "OSU_BEATMAPSET_ID",
+                self.beatmapset_id.map(|id| id.to_string()),
+            ),
+            ("OSU_FILE_PATH", self.file_path.clone()),
+            ("OSU_MIRROR", self.mirror.clone()),
+            ("OSU_ERROR", self.error.clone()),
+            ("OSU_DOWNLOADED", self.downloaded.map(|n| n.to_string())),
+            ("OSU_FAILED", self.failed.map(|n| n.to_string())),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                env.push((key, value));
+            }
+        }
+        env
+    }
+}
+
+async fn run_hook(hook: &Hook, payload: &HookPayload) -> Result<()> {
+    let mut child = Command::new(&hook.command)
+        .args(&hook.args)
+        .envs(payload.env())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Hooks that don't read stdin close it early, that's fine
+        let _ = stdin.write_all(&serde_json::to_vec(payload)?).await;
+    }
+    let timeout = Duration::from_secs(hook.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| eyre!("timed out after {:?}", timeout))??;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        log::info!("{}: {}", hook.command, line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        log::warn!("{}: {}", hook.command, line);
+    }
+    if !output.status.success() {
+        return Err(eyre!("exited with {}", output.status));
+    }
+    Ok(())
+}
+
+// Hook failures are only logged, they never stop downloads
+pub async fn run_hooks(hooks: &[Hook], payload: &HookPayload) {
+    for hook in hooks.iter().filter(|hook| hook.on == payload.event) {
+        if let Err(e) = run_hook(hook, payload).await {
+            log::warn!("hook '{}' failed: {}", hook.command, e);
+        }
+    }
+}
+
+// Runs hooks in the order the events come in, off the download workers so a slow
+// hook doesn't hold up a download slot
+pub fn run_worker(runtime: Arc<Runtime>, hooks: Vec<Hook>, rx: Receiver<HookPayload>) {
+    for payload in rx {
+        runtime.block_on(run_hooks(&hooks, &payload));
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_hook() {
+        let hook = |script: &str, timeout_secs| Hook {
+            on: HookEvent::Success,
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script.to_owned()],
+            timeout_secs,
+        };
+        let payload = HookPayload {
+            beatmapset_id: Some(1),
+            ..HookPayload::new(HookEvent::Success, "success")
+        };
+        let check = r#"test "$OSU_BEATMAPSET_ID" = 1 && grep -q '"beatmapset_id":1'"#;
+        assert!(run_hook(&hook(check, None), &payload).await.is_ok());
+        assert!(run_hook(&hook("exit 1", None), &payload).await.is_err());
+        assert!(run_hook(&hook("sleep 5", Some(1)), &payload).await.is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::client::Beatmapset;
+use crate::collections::SavedCollection;
+use crate::export::ExportEntry;
+use crate::ratelimit::RateLimiter;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportEntry {
+    Set(u32),
+    // A difficulty, resolved to its set before queueing
+    Beatmap(u32),
+}
+
+#[derive(Debug, Default)]
+pub struct ImportResult {
+    pub sets: Vec<u32>,
+    // Lines nothing was found in, and beatmaps that couldn't be resolved
+    pub skipped: Vec<String>,
+    // Metadata, when the source had it
+    pub songs: Vec<Beatmapset>,
+    // Recorded in collections.yaml once the import is done
+    pub collection: Option<SavedCollection>,
+}
+
+impl ImportResult {
+    pub fn from_songs(songs: Vec<Beatmapset>) -> Self {
+        Self {
+            sets: songs.iter().map(|song| song.id).collect(),
+            songs,
+            ..Default::default()
+        }
+    }
+}
+
+// osu! website and client links, plus mirror download links
+fn parse_url(token: &str) -> Option<ImportEntry> {
+    let token = token.trim_matches(|c: char| matches!(c, '"' | '\'' | '<' | '>' | '(' | ')'));
+    let rest = token
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(token);
+    let (host, path) = rest.split_once('/')?;
+    let path = path.split(['?', '#']).next()?;
+    let mut segments = path.split('/').filter(|s| !s.is_empty());
+    let kind = segments.next()?;
+    let id = segments.next().and_then(|id| id.parse().ok());
+    match kind {
+        // `/beatmapsets/123#osu/456` names the set already
+        "beatmapsets" | "s" | "d" => id.map(ImportEntry::Set),
+        "beatmaps" | "b" => id.map(ImportEntry::Beatmap),
+        // `osu://s/123` has the kind as host
+        _ if host == "s" || host == "dl" => kind.parse().ok().map(ImportEntry::Set),
+        _ if host == "b" => kind.parse().ok().map(ImportEntry::Beatmap),
+        _ => None,
+    }
+}
+
+fn is_url(token: &str) -> bool {
+    token.contains("://") || token.contains(".ppy.sh/")
+}
+
+// Entries of a pasted or imported list: links, bare set IDs, CSV rows or an exported JSON list.
+// In a CSV row only links or the first numeric cell count, other cells are metadata.
+pub fn parse_list(text: &str) -> (Vec<ImportEntry>, Vec<String>) {
+    if let Ok(exported) = serde_json::from_str::<Vec<ExportEntry>>(text) {
+        let entries = exported
+            .iter()
+            .map(|entry| ImportEntry::Set(entry.id))
+            .collect();
+        return (entries, Vec::new());
+    }
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    let mut first = true;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let header = std::mem::take(&mut first);
+        let csv = line.contains(',') || line.contains(';') || line.contains('\t');
+        let tokens = line
+            .split([',', ';', '\t', ' '])
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>();
+        let mut found = tokens
+            .iter()
+            .filter(|t| is_url(t))
+            .filter_map(|t| parse_url(t))
+            .collect::<Vec<_>>();
+        if found.is_empty() {
+            let mut ids = tokens
+                .iter()
+                .filter_map(|t| t.trim_matches('"').parse().ok())
+                .map(ImportEntry::Set);
+            found = if csv {
+                ids.next().into_iter().collect()
+            } else {
+                ids.collect()
+            };
+        }
+        // A CSV header isn't worth reporting
+        if found.is_empty() && !(header && csv) {
+            skipped.push(line.to_owned());
+        }
+        entries.extend(found);
+    }
+    (entries, skipped)
+}
+
+#[derive(Deserialize)]
+struct BeatmapLookup {
+    beatmapset_id: u32,
+}
+
+// Set of a difficulty, from mirrors that look beatmaps up without credentials
+pub async fn resolve_beatmap(limiter: &RateLimiter, beatmap_id: u32) -> Result<u32> {
+    let client = reqwest::Client::new();
+    let mut last_error = eyre!("no mirror to look up beatmaps");
+    for url in [
+        format!("https://catboy.best/api/v2/b/{}", beatmap_id),
+        format!("https://osu.direct/api/v2/b/{}", beatmap_id),
+    ] {
+        let result = async {
+            let res = limiter.send(client.get(&url)).await?;
+            if !res.status().is_success() {
+                return Err(eyre!("Request failed with status: {}", res.status()));
+            }
+            Ok(res.json::<BeatmapLookup>().await?.beatmapset_id)
+        }
+        .await;
+        match result {
+            Ok(id) => return Ok(id),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+// Parse `text` and resolve its beatmaps, keeping the list order without duplicates
+pub async fn import_list(limiter: &RateLimiter, text: &str) -> ImportResult {
+    let (entries, mut skipped) = parse_list(text);
+    let mut seen = HashSet::new();
+    let mut sets = Vec::new();
+    for entry in entries {
+        let id = match entry {
+            ImportEntry::Set(id) => id,
+            ImportEntry::Beatmap(id) => match resolve_beatmap(limiter, id).await {
+                Ok(id) => id,
+                Err(e) => {
+                    skipped.push(format!("beatmap {}: {}", id, e));
+                    continue;
+                }
+            },
+        };
+        if seen.insert(id) {
+            sets.push(id);
+        }
+    }
+    ImportResult {
+        sets,
+        skipped,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ImportEntry::*;
+
+    #[test]
+    fn test_parse_list() {
+        let text = "\
+            https://osu.ppy.sh/beatmapsets/123#osu/456\n\
+            https://osu.ppy.sh/b/456?m=0\n\
+            osu.ppy.sh/s/789\n\
+            https://osu.ppy.sh/beatmaps/111\n\
+            osu://s/222\n\
+            https://api.nerinyan.moe/d/333?nv=1\n\
+            444 555\n\
+            # comment\n\
+            id,artist,title\n\
+            666,Artist,1973\n\
+            not a map\n";
+        let (entries, skipped) = parse_list(text);
+        assert_eq!(
+            entries,
+            vec![
+                Set(123),
+                Beatmap(456),
+                Set(789),
+                Beatmap(111),
+                Set(222),
+                Set(333),
+                Set(444),
+                Set(555),
+                Set(666),
+            ]
+        );
+        assert_eq!(skipped, vec!["id,artist,title", "not a map"]);
+    }
+
+    #[test]
+    fn test_parse_csv_header() {
+        let text = "\
+            # exported list\n\
+            id,artist,title\n\
+            1,Artist,Title\n\
+            unknown,Artist,Title\n";
+        let (entries, skipped) = parse_list(text);
+        assert_eq!(entries, vec![Set(1)]);
+        assert_eq!(skipped, vec!["unknown,Artist,Title"]);
+    }
+}
@@ -4,13 +4,30 @@
 
 use eframe::egui;
 use eyre::{Result, WrapErr};
-use rosu_v2::prelude::*;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use thiserror::Error;
 
+mod auth;
 mod client;
+mod collection_db;
+mod collections;
 mod downloader;
+mod estimate;
+mod export;
+mod extract;
+mod filename;
+mod hooks;
+mod import;
+mod mappool;
+mod mirror;
+mod ratelimit;
+mod search;
 mod settings;
+mod source;
+mod strip;
+mod sync;
+#[cfg(test)]
+mod test_server;
 mod utils;
 
 #[derive(Debug, Error)]
@@ -28,19 +45,58 @@ pub enum AppError {
 }
 
 fn create_search_client(
-    runtime: &Arc<tokio::runtime::Runtime>,
+    limiter: &Arc<ratelimit::RateLimiter>,
+    auth: &Arc<auth::OsuAuth>,
     config: &settings::Config,
+    name: &str,
 ) -> Result<Box<dyn client::SearchClient>, AppError> {
-    match config.search_client.as_str() {
-        "nerinyan" => Ok(Box::new(client::nerinyan::NerinyanClient::new())),
-        "osu" => Ok(Box::new(client::osu::OsuClient::new())),
-        "osu_api" => {
-            let client = runtime
-                .block_on(Osu::new(config.client_id, config.client_secret.clone()))
-                .map_err(|e| AppError::ClientCreationError(e.to_string()))?;
-            Ok(Box::new(client))
+    let filter = client::Filter {
+        mode: config.mode,
+        status: config.status,
+    };
+    match name {
+        "nerinyan" => Ok(Box::new(client::nerinyan::NerinyanClient::new(
+            limiter.clone(),
+            filter,
+        ))),
+        "osu" => Ok(Box::new(client::osu::OsuClient::new(
+            limiter.clone(),
+            filter,
+        ))),
+        "catboy" => Ok(Box::new(client::catboy::CatboyClient::new(
+            limiter.clone(),
+            filter,
+        ))),
+        "osu_direct" => Ok(Box::new(client::osu_direct::OsuDirectClient::new(
+            limiter.clone(),
+            filter,
+        ))),
+        "beatconnect" => Ok(Box::new(client::beatconnect::BeatconnectClient::new(
+            limiter.clone(),
+            config.beatconnect_token.clone(),
+            filter,
+        ))),
+        "osu_api" => Ok(Box::new(client::osu::OsuClient::api(
+            limiter.clone(),
+            auth.clone(),
+            filter,
+        ))),
+        "aggregate" => {
+            let mut clients = Vec::new();
+            for backend in config.aggregate_clients.iter() {
+                if backend == "aggregate" {
+                    return Err(AppError::ClientCreationError(
+                        "aggregate can't include itself".to_string(),
+                    ));
+                }
+                let client = create_search_client(limiter, auth, config, backend)?;
+                clients.push((backend.clone(), client));
+            }
+            Ok(Box::new(client::aggregate::AggregateClient::new(clients)))
         }
-        _ => Err(AppError::ClientCreationError("Unknown client type".to_string())),
+        _ => Err(AppError::ClientCreationError(
+            "Unknown client type".to_string(),
+        )),
     }
 }
 
@@ -79,41 +135,82 @@ fn main() -> Result<()> {
             .map_err(AppError::RuntimeError)?,
     );
 
-    // Read config with proper error handling
-    let config = settings::read_config_from_yaml("config.yaml")
-        .wrap_err("Failed to read config file")?;
+    // Shared by search clients and downloads so every mirror is throttled per host
+    let limiter = Arc::new(ratelimit::RateLimiter::default());
 
-    // Create search client with proper error handling
-    let search_client = create_search_client(&runtime, &config)?;
+    // Set by the window to reopen with another profile
+    let profile_switch = Arc::new(RwLock::new(None::<String>));
+    let mut profile = profile_arg();
+    loop {
+        // Read config with proper error handling
+        let config = settings::read_config_from_yaml("config.yaml", profile.as_deref())
+            .wrap_err("Failed to read config file")?;
 
-    // Configure main window
-    let options = eframe::NativeOptions {
-        run_and_return: true,
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([1280.0, 720.0])
-            .with_icon(icon),
-        ..Default::default()
-    };
+        // Credentials for the official osu! website, kept outside of config.yaml
+        let auth = Arc::new(auth::OsuAuth::new(
+            config.client_id,
+            config.client_secret.clone(),
+            settings::profile_path(auth::CREDENTIALS_PATH, config.profile.as_deref()),
+            limiter.clone(),
+        ));
+
+        // Create search client with proper error handling
+        let search_client = create_search_client(&limiter, &auth, &config, &config.search_client)?;
+
+        // Configure main window
+        let options = eframe::NativeOptions {
+            run_and_return: true,
+            viewport: egui::ViewportBuilder::default()
+                .with_inner_size([1280.0, 720.0])
+                .with_icon(icon.clone()),
+            ..Default::default()
+        };
+
+        let title = match &config.profile {
+            Some(profile) => format!("osu! Beatmap Downloader ({})", profile),
+            None => "osu! Beatmap Downloader".to_owned(),
+        };
+        let runtime = runtime.clone();
+        let limiter = limiter.clone();
+        let switch = profile_switch.clone();
+        // Run main application with proper error handling
+        eframe::run_native(
+            &title,
+            options,
+            Box::new(|cc| {
+                // Initialize image support
+                egui_extras::install_image_loaders(&cc.egui_ctx);
+
+                // Create and return the main application
+                Ok(downloader::BeatmapDownloaderApp::new(
+                    runtime,
+                    limiter,
+                    auth,
+                    search_client,
+                    config,
+                    switch,
+                ))
+            }),
+        )
+        .map_err(|e| AppError::ApplicationError(e.to_string()))?;
+
+        match profile_switch.write().unwrap().take() {
+            Some(next) => profile = Some(next),
+            None => return Ok(()),
+        }
+    }
+}
 
-    // Run main application with proper error handling
-    eframe::run_native(
-        "osu! Beatmap Downloader",
-        options,
-        Box::new(|cc| {
-            // Initialize image support
-            egui_extras::install_image_loaders(&cc.egui_ctx);
-
-            // Create and return the main application
-            Ok(downloader::BeatmapDownloaderApp::new(
-                runtime,
-                search_client,
-                config.songs_path,
-                config.number_of_fetch,
-                config.server,
-                config.selected_server,
-                config.number_of_simultaneous_downloads,
-            ))
-        }),
-    )
-    .map_err(|e| AppError::ApplicationError(e.to_string()).into())
+// `--profile NAME` or `--profile=NAME`
+fn profile_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--profile" || arg == "-p" {
+            return args.next();
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Some(name.to_owned());
+        }
+    }
+    None
 }
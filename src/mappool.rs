@@ -0,0 +1,190 @@
+use crate::import::resolve_beatmap;
+use crate::ratelimit::RateLimiter;
+use eyre::{eyre, Result};
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    // Like "NM1", "DT2" or "TB"
+    pub name: String,
+    pub beatmap_id: u32,
+}
+
+impl Slot {
+    // The mod group, "NM" for "NM1"
+    pub fn pool(&self) -> &str {
+        self.name.trim_end_matches(|c: char| c.is_ascii_digit())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedSlot {
+    pub slot: Slot,
+    pub set_id: Result<u32, String>,
+}
+
+fn is_slot(cell: &str) -> bool {
+    let letters = cell.trim_end_matches(|c: char| c.is_ascii_digit());
+    (2..=4).contains(&letters.len()) && letters.chars().all(|c| c.is_ascii_uppercase())
+}
+
+// A bare ID or a difficulty link, `/beatmapsets/1#osu/2` names beatmap 2
+fn beatmap_id(cell: &str) -> Option<u32> {
+    let cell = cell.trim().trim_matches('"');
+    if let Ok(id) = cell.parse() {
+        return Some(id);
+    }
+    if !cell.contains("://") && !cell.contains(".ppy.sh/") {
+        return None;
+    }
+    if let Some((_, fragment)) = cell.split_once('#') {
+        return fragment.rsplit('/').next()?.parse().ok();
+    }
+    let path = cell.split(['?']).next()?;
+    let mut segments = path.rsplit('/').filter(|s| !s.is_empty());
+    let id = segments.next()?.parse().ok()?;
+    matches!(segments.next()?, "b" | "beatmaps").then_some(id)
+}
+
+// `NM1: 123`, or `NM: [123, 456]` for numbered slots
+fn parse_yaml(mapping: &serde_yaml::Mapping) -> (Vec<Slot>, Vec<String>) {
+    let mut slots = Vec::new();
+    let mut skipped = Vec::new();
+    for (key, value) in mapping {
+        let name = match key {
+            Value::String(name) => name.trim().to_owned(),
+            other => serde_yaml::to_string(other)
+                .unwrap_or_default()
+                .trim()
+                .to_owned(),
+        };
+        let cell = |value: &Value| match value {
+            Value::Number(id) => id.as_u64().and_then(|id| u32::try_from(id).ok()),
+            Value::String(cell) => beatmap_id(cell),
+            _ => None,
+        };
+        match value {
+            Value::Sequence(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    let name = format!("{}{}", name, i + 1);
+                    match cell(value) {
+                        Some(beatmap_id) => slots.push(Slot { name, beatmap_id }),
+                        None => skipped.push(name),
+                    }
+                }
+            }
+            value => match cell(value) {
+                Some(beatmap_id) => slots.push(Slot { name, beatmap_id }),
+                None => skipped.push(name),
+            },
+        }
+    }
+    (slots, skipped)
+}
+
+// Spreadsheet rows: a slot cell followed by the beatmap ID or link, other cells are ignored
+fn parse_csv(text: &str) -> (Vec<Slot>, Vec<String>) {
+    let mut slots = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let cells = line
+            .split([',', ';', '\t'])
+            .map(|cell| cell.trim().trim_matches('"'))
+            .collect::<Vec<_>>();
+        let slot = cells.iter().position(|cell| is_slot(cell)).and_then(|i| {
+            let beatmap_id = cells[i + 1..].iter().find_map(|cell| beatmap_id(cell))?;
+            Some(Slot {
+                name: cells[i].to_owned(),
+                beatmap_id,
+            })
+        });
+        match slot {
+            Some(slot) => slots.push(slot),
+            // A header isn't worth reporting
+            None if i == 0 => {}
+            None => skipped.push(line.to_owned()),
+        }
+    }
+    (slots, skipped)
+}
+
+// Slots of a CSV or YAML mappool, and the entries that aren't slots
+pub fn parse_mappool(text: &str) -> Result<(Vec<Slot>, Vec<String>)> {
+    let (slots, skipped) = match serde_yaml::from_str::<Value>(text) {
+        Ok(Value::Mapping(mapping)) => parse_yaml(&mapping),
+        _ => parse_csv(text),
+    };
+    if slots.is_empty() {
+        return Err(eyre!("no pool slots found"));
+    }
+    Ok((slots, skipped))
+}
+
+// Look up the set of each slot, once per beatmap
+pub async fn resolve_mappool(limiter: &RateLimiter, slots: Vec<Slot>) -> Vec<ResolvedSlot> {
+    let mut sets = HashMap::<u32, Result<u32, String>>::new();
+    let mut resolved = Vec::new();
+    for slot in slots {
+        let set_id = match sets.get(&slot.beatmap_id) {
+            Some(set_id) => set_id.clone(),
+            None => {
+                let set_id = resolve_beatmap(limiter, slot.beatmap_id)
+                    .await
+                    .map_err(|e| e.to_string());
+                sets.insert(slot.beatmap_id, set_id.clone());
+                set_id
+            }
+        };
+        resolved.push(ResolvedSlot { slot, set_id });
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(slots: &[Slot]) -> Vec<(&str, u32)> {
+        slots
+            .iter()
+            .map(|slot| (slot.name.as_str(), slot.beatmap_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_mappool() {
+        let csv = "\
+            Slot,ID,Map,SR\n\
+            NM1,123,Artist - Title,5.2\n\
+            HD1,https://osu.ppy.sh/beatmapsets/9#osu/456,x,5.0\n\
+            DT1;https://osu.ppy.sh/b/789\n\
+            TB,\"1000\"\n\
+            FM1,TBD\n";
+        let (slots, skipped) = parse_mappool(csv).unwrap();
+        assert_eq!(
+            names(&slots),
+            vec![("NM1", 123), ("HD1", 456), ("DT1", 789), ("TB", 1000)]
+        );
+        assert_eq!(skipped, vec!["FM1,TBD"]);
+        assert_eq!(slots[3].pool(), "TB");
+        assert_eq!(slots[0].pool(), "NM");
+
+        let yaml = "\
+            NM: [1, 2]\n\
+            HR1: https://osu.ppy.sh/beatmaps/3\n\
+            TB: 4\n";
+        let (slots, skipped) = parse_mappool(yaml).unwrap();
+        assert_eq!(
+            names(&slots),
+            vec![("NM1", 1), ("NM2", 2), ("HR1", 3), ("TB", 4)]
+        );
+        assert!(skipped.is_empty());
+
+        assert!(parse_mappool("nothing here").is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::auth::{AuthKind, OsuAuth};
+use eyre::{eyre, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, USER_AGENT};
+use reqwest::{Method, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::time::Duration;
+use strfmt::strfmt;
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0";
+
+// Values substituted for `{no_video}` in the url template.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct NoVideo {
+    pub with_video: String,
+    pub without_video: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Mirror {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    // Extra template variables, e.g. `{api_key}`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_video: Option<NoVideo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthKind>,
+    // Lower value is tried first when falling back to other mirrors
+    #[serde(default)]
+    pub priority: i32,
+}
+
+// A bare url template is still accepted for `server` entries in older configs.
+// Those of the official site only download with a session, so they get one.
+impl FromStr for Mirror {
+    type Err = Infallible;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let official = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .starts_with("osu.ppy.sh/");
+        Ok(Self {
+            auth: official.then_some(AuthKind::Session),
+            ..Self::new(url)
+        })
+    }
+}
+
+impl Mirror {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            user_agent: None,
+            headers: HashMap::new(),
+            variables: HashMap::new(),
+            no_video: None,
+            timeout_secs: None,
+            auth: None,
+            priority: 0,
+        }
+    }
+
+    pub fn supports_no_video(&self) -> bool {
+        self.no_video.is_some()
+    }
+
+    pub fn url_for(&self, beatmap_id: u32, no_video: bool) -> Result<String> {
+        let mut vars = self.variables.clone();
+        vars.insert("beatmap_id".to_owned(), beatmap_id.to_string());
+        let no_video = match &self.no_video {
+            Some(v) if no_video => v.without_video.clone(),
+            Some(v) => v.with_video.clone(),
+            None => "".to_owned(),
+        };
+        vars.insert("no_video".to_owned(), no_video);
+        strfmt(&self.url, &vars).map_err(|e| eyre!("invalid url template '{}': {}", self.url, e))
+    }
+
+    pub fn headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        let user_agent = self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        headers.insert(USER_AGENT, HeaderValue::from_str(user_agent)?);
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        for (name, value) in self.headers.iter() {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        Ok(headers)
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    // Request for `beatmap_id` with this mirror's headers, timeout and authentication
+    pub async fn request(
+        &self,
+        client: &reqwest::Client,
+        method: Method,
+        auth: &OsuAuth,
+        beatmap_id: u32,
+        no_video: bool,
+    ) -> Result<RequestBuilder> {
+        let url = self.url_for(beatmap_id, no_video)?;
+        let mut headers = self.headers()?;
+        if let Some(kind) = self.auth {
+            headers.extend(auth.headers(kind).await?);
+        }
+        let mut request = client.request(method, url).headers(headers);
+        if let Some(timeout) = self.timeout() {
+            request = request.timeout(timeout);
+        }
+        Ok(request)
+    }
+}
+
+// Names of `mirrors` sorted by priority, then name so the order is stable.
+pub fn by_priority(mirrors: &HashMap<String, Mirror>) -> Vec<String> {
+    let mut names = mirrors.keys().cloned().collect::<Vec<String>>();
+    names.sort_by(|a, b| mirrors[a].priority.cmp(&mirrors[b].priority).then(a.cmp(b)));
+    names
+}
+
+pub fn default_mirrors() -> HashMap<String, Mirror> {
+    let mut server = HashMap::new();
+    server.insert(
+        "beatconnect".to_owned(),
+        Mirror {
+            priority: 3,
+            ..Mirror::new("https://beatconnect.io/b/{beatmap_id}")
+        },
+    );
+    server.insert(
+        "nerinyan".to_owned(),
+        Mirror {
+            no_video: Some(NoVideo {
+                with_video: "0".to_owned(),
+                without_video: "1".to_owned(),
+            }),
+            priority: 0,
+            ..Mirror::new("https://api.nerinyan.moe/d/{beatmap_id}?nv={no_video}")
+        },
+    );
+    server.insert(
+        "osu_direct".to_owned(),
+        Mirror {
+            no_video: Some(NoVideo {
+                with_video: "".to_owned(),
+                without_video: "?noVideo=1".to_owned(),
+            }),
+            priority: 2,
+            ..Mirror::new("https://osu.direct/api/d/{beatmap_id}{no_video}")
+        },
+    );
+    server.insert(
+        "catboy".to_owned(),
+        Mirror {
+            no_video: Some(NoVideo {
+                with_video: "".to_owned(),
+                without_video: "n".to_owned(),
+            }),
+            priority: 1,
+            ..Mirror::new("https://catboy.best/d/{beatmap_id}{no_video}")
+        },
+    );
+    server.insert(
+        "osu_ppy".to_owned(),
+        Mirror {
+            no_video: Some(NoVideo {
+                with_video: "".to_owned(),
+                without_video: "?noVideo=1".to_owned(),
+            }),
+            // The API's download route needs a user token, the website takes the session cookie
+            auth: Some(AuthKind::Session),
+            priority: 4,
+            ..Mirror::new("https://osu.ppy.sh/beatmapsets/{beatmap_id}/download{no_video}")
+        },
+    );
+    server
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_for() {
+        let mirrors = default_mirrors();
+        assert_eq!(
+            mirrors["nerinyan"].url_for(123, true).unwrap(),
+            "https://api.nerinyan.moe/d/123?nv=1"
+        );
+        assert_eq!(
+            mirrors["catboy"].url_for(123, false).unwrap(),
+            "https://catboy.best/d/123"
+        );
+        assert_eq!(
+            mirrors["beatconnect"].url_for(123, true).unwrap(),
+            "https://beatconnect.io/b/123"
+        );
+        assert_eq!(
+            mirrors["osu_ppy"].url_for(123, true).unwrap(),
+            "https://osu.ppy.sh/beatmapsets/123/download?noVideo=1"
+        );
+        assert_eq!(mirrors["osu_ppy"].auth, Some(AuthKind::Session));
+    }
+
+    #[test]
+    fn test_legacy_config() {
+        let config: crate::settings::Config = serde_yaml::from_str(
+            r#"
+client_id: 1
+client_secret: ""
+search_client: nerinyan
+songs_path: ""
+number_of_fetch: 50
+selected_server: nerinyan
+number_of_simultaneous_downloads: 5
+server:
+  nerinyan: "https://api.nerinyan.moe/d/{beatmap_id}"
+  catboy:
+    url: "https://catboy.best/d/{beatmap_id}{no_video}"
+    user_agent: "downloader"
+    no_video:
+      with_video: ""
+      without_video: "n"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.server["nerinyan"],
+            Mirror::new("https://api.nerinyan.moe/d/{beatmap_id}")
+        );
+        assert!(config.server["catboy"].supports_no_video());
+        assert!(!config.no_video);
+    }
+
+    #[test]
+    fn test_legacy_official_config() {
+        // Written by the configuration window before mirrors had `auth`
+        let config: crate::settings::Config = serde_yaml::from_str(
+            r#"
+client_id: 1
+client_secret: ""
+search_client: nerinyan
+songs_path: ""
+number_of_fetch: 50
+selected_server: osu_ppy
+number_of_simultaneous_downloads: 5
+server:
+  nerinyan: "https://api.nerinyan.moe/d/{beatmap_id}"
+  osu_ppy: "https://osu.ppy.sh/beatmapsets/{beatmap_id}/download"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.server["osu_ppy"].auth, Some(AuthKind::Session));
+        assert_eq!(config.server["nerinyan"].auth, None); // The OAuth client's token can't download
+        assert!(serde_yaml::from_str::<Mirror>("{url: x, auth: oauth}").is_err());
+    }
+
+    #[test]
+    fn test_example_config() {
+        let config: crate::settings::Config =
+            serde_yaml::from_str(include_str!("../config.example.yaml")).unwrap();
+        let mirrors = default_mirrors();
+        assert_eq!(config.server.len(), mirrors.len());
+        assert_eq!(config.server["nerinyan"], mirrors["nerinyan"]);
+        assert_eq!(config.server["osu_ppy"], mirrors["osu_ppy"]);
+    }
+}
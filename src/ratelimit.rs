@@ -0,0 +1,142 @@
+use eyre::{eyre, Result};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_MIN_INTERVAL: Duration = Duration::from_millis(250);
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+
+// Per-host request limiter shared by every search client and download worker.
+// Each host gets its own schedule, so a throttled mirror never slows down the others.
+pub struct RateLimiter {
+    min_interval: Duration,
+    max_retries: u32,
+    next_slot: Mutex<HashMap<String, Instant>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_INTERVAL, DEFAULT_MAX_RETRIES)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Duration, max_retries: u32) -> Self {
+        Self {
+            min_interval,
+            max_retries,
+            next_slot: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Reserve the next free slot for `host` and wait until it comes up.
+    async fn acquire(&self, host: &str) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = match next_slot.get(host) {
+                Some(next) if *next > now => *next,
+                _ => now,
+            };
+            next_slot.insert(host.to_owned(), slot + self.min_interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    // Push every pending and future request for `host` back by `delay`.
+    fn defer(&self, host: &str, delay: Duration) {
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let until = Instant::now() + delay;
+        let entry = next_slot.entry(host.to_owned()).or_insert(until);
+        if *entry < until {
+            *entry = until;
+        }
+    }
+
+    // Send a request through the limiter, retrying on 429/503 and honoring `Retry-After`.
+    pub async fn send(&self, request: reqwest::RequestBuilder) -> Result<Response> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let host = request.url().host_str().unwrap_or_default().to_owned();
+
+        let mut backoff = DEFAULT_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let req = try_clone(&request)?;
+            self.acquire(&host).await;
+            let res = client.execute(req).await?;
+            if !is_throttled(res.status()) || attempt >= self.max_retries {
+                return Ok(res);
+            }
+            attempt += 1;
+            let delay = retry_after(&res).unwrap_or(backoff).min(MAX_BACKOFF);
+            log::warn!(
+                "{} throttled ({}), retrying in {:?} ({}/{})",
+                host,
+                res.status(),
+                delay,
+                attempt,
+                self.max_retries
+            );
+            self.defer(&host, delay);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+fn try_clone(request: &Request) -> Result<Request> {
+    request
+        .try_clone()
+        .ok_or_else(|| eyre!("request body can't be retried"))
+}
+
+fn is_throttled(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    parse_retry_after(value)
+}
+
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.signed_duration_since(chrono::Utc::now());
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_spaces_requests_per_host() {
+        let limiter = RateLimiter::new(Duration::from_millis(50), 0);
+        let start = Instant::now();
+        limiter.acquire("a").await;
+        limiter.acquire("b").await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        limiter.acquire("a").await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::client::{Beatmapset, SearchClient};
+use crate::sync::PendingSync;
+use chrono::{DateTime, Utc};
+use eyre::eyre;
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, RwLock};
+use tokio::runtime::Runtime;
+
+// How far a search goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchLimit {
+    Pages(u32),
+    // Everything ranked since then
+    Since(DateTime<Utc>),
+    // Everything newer than this set, for backends without ranked dates
+    UntilId(u32),
+    // The whole result set
+    All,
+}
+
+// Newest set a search has seen, the checkpoint for the next incremental sync
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Newest {
+    pub id: u32,
+    pub ranked_date: Option<DateTime<Utc>>,
+}
+
+// Where a search starts or continues from
+#[derive(Debug, Clone)]
+pub struct SearchPosition {
+    pub page: u32,
+    pub limit: SearchLimit,
+    pub cursor: Option<String>,
+    pub newest: Option<Newest>,
+}
+
+impl SearchPosition {
+    pub fn start(limit: SearchLimit) -> Self {
+        Self {
+            page: 0,
+            limit,
+            cursor: None,
+            newest: None,
+        }
+    }
+
+    pub fn resume(pending: PendingSync, limit: SearchLimit) -> Self {
+        Self {
+            page: pending.page,
+            limit,
+            cursor: pending.cursor,
+            newest: pending.newest_id.map(|id| Newest {
+                id,
+                ranked_date: pending.newest_ranked_date,
+            }),
+        }
+    }
+
+    pub fn pending(&self) -> PendingSync {
+        PendingSync {
+            page: self.page,
+            cursor: self.cursor.clone(),
+            newest_id: self.newest.map(|newest| newest.id),
+            newest_ranked_date: self.newest.and_then(|newest| newest.ranked_date),
+            found: Default::default(),
+        }
+    }
+}
+
+// Where a search stopped, so it can be retried from there
+#[derive(Debug, Clone)]
+pub struct SearchFailure {
+    pub position: SearchPosition,
+    pub message: String,
+}
+
+// Sent by the search worker as each page of results arrives
+pub enum SearchUpdate {
+    // Songs missing locally, and how many songs the page had within the limit
+    Page(Vec<Beatmapset>, usize),
+    // Position of the next page, once the previous one has been sent
+    Position(SearchPosition),
+    // Part of the results are missing, the songs found so far are kept
+    Partial(String),
+    Failed(SearchFailure),
+    Done,
+}
+
+// Songs of `page` inside `limit`, and whether the search has gone past it.
+// Date searches end when the client has no next page, `fetch_page_since` knows when it's done.
+fn within_limit(songs: Vec<Beatmapset>, limit: SearchLimit) -> (Vec<Beatmapset>, bool) {
+    match limit {
+        SearchLimit::Pages(_) | SearchLimit::All => (songs, false),
+        SearchLimit::Since(since) => {
+            let songs = songs
+                .into_iter()
+                .filter(|s| s.ranked_date.is_none_or(|d| d >= since))
+                .collect();
+            (songs, false)
+        }
+        SearchLimit::UntilId(id) => match songs.iter().position(|s| s.id == id) {
+            Some(i) => (songs.into_iter().take(i).collect(), true),
+            None => (songs, false),
+        },
+    }
+}
+
+// Runs until the app drops its sender
+pub fn run_worker(
+    runtime: Arc<Runtime>,
+    search_client: Box<dyn SearchClient>,
+    rx: Receiver<SearchPosition>,
+    tx: Sender<SearchUpdate>,
+    local_songs: Arc<RwLock<HashSet<u32>>>,
+) {
+    while let Ok(mut position) = rx.recv() {
+        let since = match position.limit {
+            SearchLimit::Since(since) => Some(since),
+            _ => None,
+        };
+        loop {
+            if let SearchLimit::Pages(pages) = position.limit {
+                if position.page >= pages {
+                    break;
+                }
+            }
+            // A panicking client must not take the worker down with it
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                runtime.block_on(search_client.fetch_page_since(position.cursor.clone(), since))
+            }))
+            .unwrap_or_else(|_| Err(eyre!("search client panicked")));
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    let _ = tx.send(SearchUpdate::Failed(SearchFailure {
+                        position,
+                        message: e.to_string(),
+                    }));
+                    break;
+                }
+            };
+            for (name, e) in result.failed.iter() {
+                let _ = tx.send(SearchUpdate::Partial(format!("{} failed: {}", name, e)));
+            }
+            if position.newest.is_none() {
+                position.newest = result.songs.first().map(|song| Newest {
+                    id: song.id,
+                    ranked_date: song.ranked_date,
+                });
+            }
+            let (songs, passed) = within_limit(result.songs, position.limit);
+            let local_songs = local_songs.read().unwrap();
+            let seen = songs.len();
+            let new_songs = songs
+                .into_iter()
+                .filter(|song| !local_songs.contains(&song.id))
+                .collect();
+            let _ = tx.send(SearchUpdate::Page(new_songs, seen));
+            if passed || result.next.is_none() {
+                break;
+            }
+            position.cursor = result.next;
+            position.page += 1;
+            let _ = tx.send(SearchUpdate::Position(position.clone()));
+        }
+        let _ = tx.send(SearchUpdate::Done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(id: u32, ranked_date: Option<&str>) -> Beatmapset {
+        Beatmapset {
+            id,
+            ranked_date: ranked_date
+                .map(|d| DateTime::parse_from_rfc3339(d).unwrap().with_timezone(&Utc)),
+            ..Default::default()
+        }
+    }
+
+    fn ids(songs: &[Beatmapset]) -> Vec<u32> {
+        songs.iter().map(|s| s.id).collect()
+    }
+
+    #[test]
+    fn test_within_limit() {
+        let page = || {
+            vec![
+                song(3, Some("2024-03-01T00:00:00Z")),
+                song(2, None),
+                song(1, Some("2024-01-01T00:00:00Z")),
+            ]
+        };
+        let (songs, passed) = within_limit(page(), SearchLimit::Pages(1));
+        assert_eq!((ids(&songs), passed), (vec![3, 2, 1], false));
+
+        let since = DateTime::parse_from_rfc3339("2024-02-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let (songs, passed) = within_limit(page(), SearchLimit::Since(since));
+        assert_eq!((ids(&songs), passed), (vec![3, 2], false));
+
+        let (songs, passed) = within_limit(page(), SearchLimit::UntilId(2));
+        assert_eq!((ids(&songs), passed), (vec![3], true));
+
+        let (songs, passed) = within_limit(page(), SearchLimit::UntilId(4));
+        assert_eq!((ids(&songs), passed), (vec![3, 2, 1], false));
+    }
+}
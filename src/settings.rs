@@ -1,69 +1,233 @@
+use crate::auth::{Credentials, CREDENTIALS_PATH};
+use crate::client::{Mode, Status};
+use crate::filename::sanitize_filename;
+use crate::hooks::Hook;
+use crate::mirror::{default_mirrors, Mirror};
+use chrono::NaiveDate;
 use eframe::egui;
-use eyre::Result;
+use eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use serde_yaml::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
 pub fn check_config_file() -> bool {
     let config_path = std::path::Path::new("config.yaml");
     config_path.exists()
 }
 
-pub fn read_config_from_yaml(file_path: &str) -> Result<Config> {
+// Settings of a profile replace the shared ones, mappings like `server` are merged key by key
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// Url-only `server` entries become mappings, so a profile can override one of their fields
+fn normalize_servers(value: &mut Value) -> Result<()> {
+    let Some(Value::Mapping(servers)) = value.get_mut("server") else {
+        return Ok(());
+    };
+    for (_, server) in servers.iter_mut() {
+        if let Value::String(url) = server {
+            *server = serde_yaml::to_value(url.parse::<Mirror>()?)?;
+        }
+    }
+    Ok(())
+}
+
+// Sync progress, history, collections and credentials of a profile, "sync_state.lazer.yaml"
+// for "sync_state.yaml", so profiles don't overwrite each other's
+pub fn profile_path(path: &str, profile: Option<&str>) -> PathBuf {
+    let Some(profile) = profile.and_then(sanitize_filename) else {
+        return PathBuf::from(path);
+    };
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, profile, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, profile),
+    };
+    path.with_file_name(name)
+}
+
+// `profile` overrides the default profile of the file, an empty name means the shared settings
+pub fn read_config_from_yaml(file_path: &str, profile: Option<&str>) -> Result<Config> {
     let file = std::fs::File::open(file_path)?;
-    let config: Config = serde_yaml::from_reader(file)?;
+    let mut value: Value = serde_yaml::from_reader(file)?;
+    let profile = match profile {
+        Some("") => None,
+        Some(profile) => Some(profile.to_owned()),
+        None => value
+            .get("profile")
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+    };
+    if let Some(name) = &profile {
+        let profiles = value.get("profiles").and_then(Value::as_mapping);
+        let mut overlay = profiles
+            .and_then(|profiles| profiles.get(name.as_str()))
+            .cloned()
+            .ok_or_else(|| {
+                let names = profiles
+                    .map(|profiles| {
+                        profiles
+                            .keys()
+                            .filter_map(Value::as_str)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    })
+                    .unwrap_or_default();
+                eyre!("unknown profile '{}', available: {}", name, names)
+            })?;
+        if let Value::Mapping(overlay) = &mut overlay {
+            overlay.remove("profiles");
+            overlay.remove("profile");
+        }
+        normalize_servers(&mut value)?;
+        merge(&mut value, overlay);
+    }
+    let mut config: Config = serde_yaml::from_value(value)?;
+    config.profile = profile;
     Ok(config)
 }
 
+#[serde_as]
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     pub client_id: u64,
     pub client_secret: String,
+    #[serde(default)]
+    pub beatconnect_token: String,
     pub search_client: String,
+    // Backends queried together when `search_client` is "aggregate"
+    #[serde(default = "default_aggregate_clients")]
+    pub aggregate_clients: Vec<String>,
     pub songs_path: String,
+    // Separate folder for downloads, like osu!'s import folder
+    #[serde(default)]
+    pub download_path: Option<String>,
+    // Name of downloaded files from {id}, {artist}, {title} and {creator},
+    // the mirror's name when unset
+    #[serde(default)]
+    pub filename_template: Option<String>,
     pub number_of_fetch: u32,
+    #[serde(default)]
+    pub fetch_mode: FetchMode,
+    // Game mode and rank status the search client lists
+    #[serde(default)]
+    pub mode: Mode,
+    #[serde(default)]
+    pub status: Status,
+    // Cutoff for `FetchMode::SinceDate`, as YYYY-MM-DD
+    #[serde(default)]
+    pub since_date: Option<NaiveDate>,
     pub selected_server: String,
     pub number_of_simultaneous_downloads: u64,
-    pub server: HashMap<String, String>,
+    #[serde(default)]
+    pub no_video: bool,
+    // Unpack each .osz into a folder of `songs_path` and remove the archive
+    #[serde(default)]
+    pub extract: bool,
+    // Rewrite downloads without video files, or storyboard files and images
+    #[serde(default)]
+    pub strip_video: bool,
+    #[serde(default)]
+    pub strip_storyboard: bool,
+    // Base URL of a source serving the API's beatmap pack routes, the osu! API when unset
+    #[serde(default)]
+    pub pack_source: Option<String>,
+    // Base URL of osu!collector, or a stand-in serving the same routes
+    #[serde(default)]
+    pub collector_url: Option<String>,
+    // osu!stable's collection.db, next to the songs folder when unset
+    #[serde(default)]
+    pub collection_db: Option<String>,
+    // Commands run after each download, failure and batch
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    #[serde_as(as = "HashMap<_, PickFirst<(_, DisplayFromStr)>>")]
+    pub server: HashMap<String, Mirror>,
+    // Profile used when none is given on the command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    // Named sets of settings that override the ones above
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchMode {
+    // A fixed number of pages of 50
+    #[default]
+    Pages,
+    // Everything ranked since `since_date`
+    SinceDate,
+    // Everything ranked since the last complete sync
+    SinceLastSync,
+    // Only what's new since the search client's checkpoint
+    Incremental,
+    // The complete result set, resumable across runs
+    Backfill,
+}
+
+fn default_aggregate_clients() -> Vec<String> {
+    vec![
+        "nerinyan".to_owned(),
+        "catboy".to_owned(),
+        "osu_direct".to_owned(),
+    ]
 }
 
 pub struct ConfigApp {
     config: Config,
+    session_cookie: String,
 }
 
 impl ConfigApp {
     pub fn new() -> Self {
-        let mut server = HashMap::new();
-        server.insert(
-            "beatconnect".to_owned(),
-            "https://beatconnect.io/b/{beatmap_id}".to_owned(),
-        );
-        server.insert(
-            "nerinyan".to_owned(),
-            "https://api.nerinyan.moe/d/{beatmap_id}".to_owned(),
-        );
-        server.insert(
-            "osu_direct".to_owned(),
-            "https://osu.direct/api/d/{beatmap_id}".to_owned(),
-        );
-        server.insert(
-            "catboy".to_owned(),
-            "https://catboy.best/d/{beatmap_id}".to_owned(),
-        );
-        server.insert(
-            "osu_ppy".to_owned(),
-            "https://osu.ppy.sh/beatmapsets/{beatmap_id}/download".to_owned(),
-        );
         Self {
             config: Config {
                 client_id: 0,
                 client_secret: "".to_owned(),
+                beatconnect_token: "".to_owned(),
                 songs_path: "".to_owned(),
+                download_path: None,
+                filename_template: None,
                 number_of_fetch: 250,
+                fetch_mode: FetchMode::Pages,
+                mode: Mode::Osu,
+                status: Status::Ranked,
+                since_date: None,
                 selected_server: "nerinyan".to_owned(),
                 number_of_simultaneous_downloads: 5,
-                server: server,
+                no_video: false,
+                extract: false,
+                strip_video: false,
+                strip_storyboard: false,
+                pack_source: None,
+                collector_url: None,
+                collection_db: None,
+                hooks: Vec::new(),
+                server: default_mirrors(),
+                profile: None,
+                profiles: BTreeMap::new(),
                 search_client: "nerinyan".to_owned(),
+                aggregate_clients: default_aggregate_clients(),
             },
+            session_cookie: "".to_owned(),
         }
     }
 }
@@ -73,7 +237,15 @@ impl eframe::App for ConfigApp {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Configuration");
 
-            let options = vec!["nerinyan", "osu", "osu_api"];
+            let options = vec![
+                "nerinyan",
+                "catboy",
+                "osu_direct",
+                "beatconnect",
+                "osu",
+                "osu_api",
+                "aggregate",
+            ];
             egui::ComboBox::from_label("Select a Search Option")
                 .selected_text(self.config.search_client.clone())
                 .show_ui(ui, |ui| {
@@ -87,6 +259,15 @@ impl eframe::App for ConfigApp {
                 });
             match self.config.search_client.as_str() {
                 "nerinyan" => (),
+                "catboy" => (),
+                "osu_direct" => (),
+                "beatconnect" => {
+                    ui.horizontal(|ui| {
+                        let token_label = ui.label("beatconnect token: ");
+                        ui.text_edit_singleline(&mut self.config.beatconnect_token)
+                            .labelled_by(token_label.id);
+                    });
+                }
                 "osu" => (),
                 "osu_api" => {
                     ui.horizontal(|ui| {
@@ -101,6 +282,19 @@ impl eframe::App for ConfigApp {
                             .labelled_by(client_secret_label.id);
                     });
                 }
+                "aggregate" => {
+                    // Backends without extra credentials can be searched together
+                    for option in ["nerinyan", "catboy", "osu_direct", "osu"] {
+                        let mut enabled = self.config.aggregate_clients.iter().any(|c| c == option);
+                        if ui.checkbox(&mut enabled, option).changed() {
+                            if enabled {
+                                self.config.aggregate_clients.push(option.to_owned());
+                            } else {
+                                self.config.aggregate_clients.retain(|c| c != option);
+                            }
+                        }
+                    }
+                }
                 _ => (),
             }
 
@@ -113,6 +307,12 @@ impl eframe::App for ConfigApp {
                 ui.label(format!("Songs path: {}", &self.config.songs_path));
             });
 
+            ui.horizontal(|ui| {
+                let session_cookie_label = ui.label("osu! session cookie (optional): ");
+                ui.add(egui::TextEdit::singleline(&mut self.session_cookie).password(true))
+                    .labelled_by(session_cookie_label.id);
+            });
+
             if ui.button("Save").clicked() {
                 ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
             }
@@ -122,5 +322,107 @@ impl eframe::App for ConfigApp {
         let config_path = "config.yaml";
         let config_file = std::fs::File::create(config_path).unwrap();
         serde_yaml::to_writer(config_file, &self.config).unwrap();
+        if !self.session_cookie.is_empty() {
+            let path = std::path::Path::new(CREDENTIALS_PATH);
+            let mut credentials = Credentials::load(path).unwrap_or_default();
+            credentials.session_cookie = Some(self.session_cookie.clone());
+            if let Err(e) = credentials.save(path) {
+                log::error!("failed to store the session cookie: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        let path = std::env::temp_dir().join(format!("config_test_{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            "\
+client_id: 1
+client_secret: secret
+search_client: nerinyan
+songs_path: stable/Songs
+number_of_fetch: 50
+selected_server: nerinyan
+number_of_simultaneous_downloads: 5
+server:
+  nerinyan:
+    url: \"https://api.nerinyan.moe/d/{beatmap_id}\"
+    priority: 0
+  catboy: \"https://catboy.best/d/{beatmap_id}\"
+profile: stable
+profiles:
+  stable: {}
+  lazer:
+    songs_path: lazer/import
+    search_client: catboy
+    fetch_mode: incremental
+    mode: mania
+    status: loved
+    server:
+      nerinyan:
+        priority: 5
+      catboy:
+        priority: 2
+",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let config = read_config_from_yaml(path, None).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("stable"));
+        assert_eq!(config.songs_path, "stable/Songs");
+        assert_eq!(
+            config.profiles.keys().collect::<Vec<_>>(),
+            vec!["lazer", "stable"]
+        );
+
+        let config = read_config_from_yaml(path, Some("lazer")).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("lazer"));
+        assert_eq!(config.songs_path, "lazer/import");
+        assert_eq!(config.search_client, "catboy");
+        assert_eq!(config.fetch_mode, FetchMode::Incremental);
+        assert_eq!(config.number_of_fetch, 50);
+        assert_eq!((config.mode, config.status), (Mode::Mania, Status::Loved));
+        assert_eq!(config.server["nerinyan"].priority, 5);
+        // The url-only entry keeps its url when a profile changes its priority
+        assert_eq!(
+            config.server["catboy"].url,
+            "https://catboy.best/d/{beatmap_id}"
+        );
+        assert_eq!(config.server["catboy"].priority, 2);
+
+        let config = read_config_from_yaml(path, Some("")).unwrap();
+        assert_eq!(config.profile, None);
+        assert_eq!(config.songs_path, "stable/Songs");
+        assert_eq!(config.mode, Mode::Osu);
+        assert_eq!(config.server["catboy"].priority, 0);
+
+        let e = read_config_from_yaml(path, Some("mania")).unwrap_err();
+        assert!(e.to_string().contains("stable, lazer"));
+        std::fs::remove_file(path).unwrap();
+
+        read_config_from_yaml("config.example.yaml", None).unwrap();
+    }
+
+    #[test]
+    fn test_profile_path() {
+        assert_eq!(
+            profile_path("sync_state.yaml", None),
+            Path::new("sync_state.yaml")
+        );
+        assert_eq!(
+            profile_path("sync_state.yaml", Some("lazer")),
+            Path::new("sync_state.lazer.yaml")
+        );
+        assert_eq!(
+            profile_path("state/history.yaml", Some("a/b")),
+            Path::new("state/history.a_b.yaml")
+        );
     }
 }
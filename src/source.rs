@@ -0,0 +1,332 @@
+pub mod collector;
+pub mod packs;
+
+use crate::auth::OsuAuth;
+use crate::client::Beatmapset;
+use crate::ratelimit::RateLimiter;
+use eyre::{eyre, Result};
+use reqwest::StatusCode;
+use rosu_v2::prelude::{GameMode, Score, UserId};
+use rosu_v2::Osu;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+pub const API_URL: &str = "https://osu.ppy.sh/api/v2";
+const PAGE_SIZE: usize = 100;
+
+// Lists on a user's profile. rosu-v2 has no favourites, so these use the API directly.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserCategory {
+    Favourite,
+    Ranked,
+    Loved,
+    Guest,
+    Nominated,
+    Pending,
+    Graveyard,
+    MostPlayed,
+}
+
+impl UserCategory {
+    pub const ALL: [UserCategory; 8] = [
+        UserCategory::Favourite,
+        UserCategory::Ranked,
+        UserCategory::Loved,
+        UserCategory::Guest,
+        UserCategory::Nominated,
+        UserCategory::Pending,
+        UserCategory::Graveyard,
+        UserCategory::MostPlayed,
+    ];
+
+    fn path(&self) -> &'static str {
+        match self {
+            UserCategory::Favourite => "favourite",
+            UserCategory::Ranked => "ranked",
+            UserCategory::Loved => "loved",
+            UserCategory::Guest => "guest",
+            UserCategory::Nominated => "nominated",
+            UserCategory::Pending => "pending",
+            UserCategory::Graveyard => "graveyard",
+            UserCategory::MostPlayed => "most_played",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            UserCategory::Favourite => "Favourites",
+            UserCategory::Ranked => "Ranked",
+            UserCategory::Loved => "Loved",
+            UserCategory::Guest => "Guest difficulties",
+            UserCategory::Nominated => "Nominated",
+            UserCategory::Pending => "Pending",
+            UserCategory::Graveyard => "Graveyard",
+            UserCategory::MostPlayed => "Most played",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct MostPlayed {
+    beatmapset: Beatmapset,
+}
+
+// JSON from the osu! API, or an unauthenticated stand-in when `auth` is `None`
+pub(crate) async fn get<T: DeserializeOwned>(
+    limiter: &RateLimiter,
+    auth: Option<&OsuAuth>,
+    url: &str,
+) -> Result<T> {
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(auth) = auth {
+        request = request.headers(auth.api_headers().await?);
+    }
+    let res = limiter.send(request).await?;
+    if let (Some(auth), StatusCode::UNAUTHORIZED) = (auth, res.status()) {
+        auth.invalidate().await;
+    }
+    if !res.status().is_success() {
+        return Err(eyre!("Request failed with status: {}", res.status()));
+    }
+    Ok(res.json().await?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UserLookup<'a> {
+    Id(u32),
+    Name(&'a str),
+}
+
+// "@name" is always a username, a number is tried as an ID first since usernames can be numeric
+fn user_lookups(user: &str) -> Vec<UserLookup<'_>> {
+    let user = user.trim();
+    if let Some(name) = user.strip_prefix('@') {
+        return vec![UserLookup::Name(name)];
+    }
+    match user.parse() {
+        Ok(id) => vec![UserLookup::Id(id), UserLookup::Name(user)],
+        Err(_) => vec![UserLookup::Name(user)],
+    }
+}
+
+async fn lookup_user(
+    limiter: &RateLimiter,
+    auth: Option<&OsuAuth>,
+    base_url: &str,
+    user: &str,
+) -> Result<u32> {
+    let mut last_error = eyre!("no user given");
+    for lookup in user_lookups(user) {
+        let url = match lookup {
+            UserLookup::Id(id) => format!("{}/users/{}", base_url, id),
+            UserLookup::Name(name) => format!("{}/users/@{}", base_url, urlencoding::encode(name)),
+        };
+        match get::<User>(limiter, auth, &url).await {
+            Ok(user) => return Ok(user.id),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(eyre!("can't find user '{}': {}", user.trim(), last_error))
+}
+
+pub async fn user_id(limiter: &RateLimiter, auth: &OsuAuth, user: &str) -> Result<u32> {
+    lookup_user(limiter, Some(auth), API_URL, user).await
+}
+
+// Every set in one of `user`'s profile lists, without duplicates
+pub async fn user_beatmapsets(
+    limiter: &RateLimiter,
+    auth: &OsuAuth,
+    user: &str,
+    category: UserCategory,
+) -> Result<Vec<Beatmapset>> {
+    let id = user_id(limiter, auth, user).await?;
+    let mut seen = HashSet::new();
+    let mut songs = Vec::new();
+    for offset in (0..).step_by(PAGE_SIZE) {
+        let url = format!(
+            "{}/users/{}/beatmapsets/{}?limit={}&offset={}",
+            API_URL,
+            id,
+            category.path(),
+            PAGE_SIZE,
+            offset
+        );
+        let page = match category {
+            UserCategory::MostPlayed => get::<Vec<MostPlayed>>(limiter, Some(auth), &url)
+                .await?
+                .into_iter()
+                .map(|played| played.beatmapset)
+                .collect(),
+            _ => get::<Vec<Beatmapset>>(limiter, Some(auth), &url).await?,
+        };
+        let count = page.len();
+        songs.extend(page.into_iter().filter(|song| seen.insert(song.id)));
+        if count < PAGE_SIZE {
+            break;
+        }
+    }
+    Ok(songs)
+}
+
+// ID of `user` through rosu-v2, with the same lookups as `user_id`
+async fn rosu_user(osu: &Osu, user: &str) -> Result<u32> {
+    let mut last_error = eyre!("no user given");
+    for lookup in user_lookups(user) {
+        let id = match lookup {
+            UserLookup::Id(id) => UserId::from(id),
+            UserLookup::Name(name) => UserId::from(name),
+        };
+        match osu.user(id).await {
+            Ok(user) => return Ok(user.user_id),
+            Err(e) => last_error = e.into(),
+        }
+    }
+    Err(eyre!("can't find user '{}': {}", user.trim(), last_error))
+}
+
+// Scores without their mapset only give the set's ID, the metadata is left empty
+fn score_beatmapset(score: &Score) -> Option<Beatmapset> {
+    match (&score.mapset, &score.map) {
+        (Some(mapset), _) => Some(Beatmapset {
+            id: mapset.mapset_id,
+            title: mapset.title.clone(),
+            artist: mapset.artist.clone(),
+            creator: mapset.creator_name.to_string(),
+            ranked_date: None,
+        }),
+        (None, Some(map)) => Some(Beatmapset {
+            id: map.mapset_id,
+            ..Default::default()
+        }),
+        (None, None) => None,
+    }
+}
+
+// Sets of `user`'s top plays and/or recent scores in `mode`, without duplicates
+pub async fn user_score_beatmapsets(
+    osu: &Osu,
+    user: &str,
+    mode: GameMode,
+    best: bool,
+    recent: bool,
+) -> Result<Vec<Beatmapset>> {
+    let user = rosu_user(osu, user).await?;
+    let mut scores = Vec::new();
+    if best {
+        // The API keeps 200 top plays, 100 at a time
+        for offset in [0, 100] {
+            let page = osu
+                .user_scores(user)
+                .mode(mode)
+                .best()
+                .limit(100)
+                .offset(offset)
+                .await?;
+            let count = page.len();
+            scores.extend(page);
+            if count < 100 {
+                break;
+            }
+        }
+    }
+    if recent {
+        let page = osu
+            .user_scores(user)
+            .mode(mode)
+            .recent()
+            .include_fails(true)
+            .limit(100)
+            .await?;
+        scores.extend(page);
+    }
+    Ok(score_beatmapsets(&scores))
+}
+
+fn score_beatmapsets(scores: &[Score]) -> Vec<Beatmapset> {
+    let mut seen = HashSet::new();
+    scores
+        .iter()
+        .filter_map(score_beatmapset)
+        .filter(|song| seen.insert(song.id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn test_user_lookups() {
+        assert_eq!(
+            user_lookups(" 123 "),
+            vec![UserLookup::Id(123), UserLookup::Name("123")]
+        );
+        assert_eq!(user_lookups("@123"), vec![UserLookup::Name("123")]);
+        assert_eq!(user_lookups("peppy"), vec![UserLookup::Name("peppy")]);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_user() {
+        // No user has ID 123, but someone is called that
+        let base_url = test_server::serve(vec![
+            ("/users/2", r#"{"id": 2}"#),
+            ("/users/@123", r#"{"id": 5}"#),
+        ])
+        .await;
+        let limiter = RateLimiter::default();
+        assert_eq!(
+            lookup_user(&limiter, None, &base_url, "2").await.unwrap(),
+            2
+        );
+        assert_eq!(
+            lookup_user(&limiter, None, &base_url, "123").await.unwrap(),
+            5
+        );
+        assert!(lookup_user(&limiter, None, &base_url, "@2").await.is_err());
+    }
+
+    #[test]
+    fn test_score_beatmapsets() {
+        let score = |id: u32, mapset: &str| {
+            format!(
+                r#"{{"mods": [], "statistics": {{}}, "id": {}, "rank": "A", "type": "solo_score",
+                "user_id": 1, "accuracy": 0.9, "ended_at": "2024-01-01T00:00:00Z", "max_combo": 1,
+                "passed": true, "ruleset_id": 0, "total_score": 1, "replay": false,
+                "current_user_attributes": {{"pin": null}}{}}}"#,
+                id, mapset
+            )
+        };
+        let mapset = r#", "beatmapset": {"id": 10, "artist": "Band", "artist_unicode": "Band",
+            "title": "Song", "title_unicode": "Song", "creator": "Mapper", "user_id": 2,
+            "covers": {"cover": "", "cover@2x": "", "card": "", "card@2x": "", "list": "",
+            "list@2x": "", "slimcover": "", "slimcover@2x": ""}, "favourite_count": 0,
+            "nsfw": false, "offset": 0, "play_count": 0, "preview_url": "", "source": "",
+            "status": "ranked", "spotlight": false, "video": false}"#;
+        let json = format!(
+            "[{}, {}, {}]",
+            score(1, mapset),
+            score(2, mapset),
+            score(3, "")
+        );
+        let scores: Vec<Score> = serde_json::from_str(&json).unwrap();
+        let songs = score_beatmapsets(&scores);
+        assert_eq!(songs.len(), 1);
+        assert_eq!(
+            (
+                songs[0].id,
+                songs[0].title.as_str(),
+                songs[0].artist.as_str()
+            ),
+            (10, "Song", "Band")
+        );
+    }
+}
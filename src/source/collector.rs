@@ -0,0 +1,173 @@
+use super::get;
+use crate::client::Beatmapset;
+use crate::ratelimit::RateLimiter;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+pub const DEFAULT_COLLECTOR_URL: &str = "https://osucollector.com";
+const PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct Collection {
+    pub id: u32,
+    pub name: String,
+    pub beatmapsets: Vec<u32>,
+    // Metadata of the sets osu!collector returned it for
+    pub songs: Vec<Beatmapset>,
+    // MD5 of each difficulty, what osu!'s collection.db stores
+    pub checksums: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CollectionInfo {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct BeatmapPage {
+    #[serde(default)]
+    beatmaps: Vec<CollectorBeatmap>,
+    #[serde(default, rename = "hasMore")]
+    has_more: bool,
+    #[serde(default, rename = "nextPageCursor")]
+    next_page_cursor: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct CollectorBeatmap {
+    #[serde(default)]
+    checksum: Option<String>,
+    beatmapset_id: u32,
+    #[serde(default)]
+    beatmapset: Option<Beatmapset>,
+}
+
+// "123" or a collection link like "https://osucollector.com/collections/123/name"
+pub fn collection_id(input: &str) -> Option<u32> {
+    let input = input.trim();
+    if let Ok(id) = input.parse() {
+        return Some(id);
+    }
+    let mut segments = input.split(['/', '?', '#']);
+    segments.find(|s| *s == "collections")?;
+    segments.next()?.parse().ok()
+}
+
+pub struct CollectorClient {
+    base_url: String,
+    limiter: Arc<RateLimiter>,
+}
+
+impl CollectorClient {
+    pub fn new(base_url: Option<String>, limiter: Arc<RateLimiter>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_COLLECTOR_URL.to_owned());
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            limiter,
+        }
+    }
+
+    pub async fn collection(&self, input: &str) -> Result<Collection> {
+        let id = collection_id(input)
+            .ok_or_else(|| eyre!("'{}' is not a collection ID or link", input))?;
+        let url = format!("{}/api/collections/{}", self.base_url, id);
+        let info = get::<CollectionInfo>(&self.limiter, None, &url).await?;
+        let mut collection = Collection {
+            id,
+            name: info.name,
+            ..Default::default()
+        };
+        let mut seen = HashSet::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut url = format!(
+                "{}/api/collections/{}/beatmapsv2?perPage={}",
+                self.base_url, id, PAGE_SIZE
+            );
+            if let Some(cursor) = &cursor {
+                url += &format!("&cursor={}", urlencoding::encode(cursor));
+            }
+            let page = get::<BeatmapPage>(&self.limiter, None, &url).await?;
+            for beatmap in page.beatmaps {
+                collection.checksums.extend(beatmap.checksum);
+                if !seen.insert(beatmap.beatmapset_id) {
+                    continue;
+                }
+                collection.beatmapsets.push(beatmap.beatmapset_id);
+                if let Some(song) = beatmap.beatmapset {
+                    collection.songs.push(Beatmapset {
+                        id: beatmap.beatmapset_id,
+                        ..song
+                    });
+                }
+            }
+            // The cursor is a number or a string depending on the sort
+            cursor = match page.next_page_cursor {
+                Some(serde_json::Value::String(cursor)) => Some(cursor),
+                Some(serde_json::Value::Number(cursor)) => Some(cursor.to_string()),
+                _ => None,
+            };
+            if !page.has_more || cursor.is_none() {
+                break;
+            }
+        }
+        Ok(collection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn test_collection_id() {
+        assert_eq!(collection_id("123"), Some(123));
+        assert_eq!(
+            collection_id("https://osucollector.com/collections/123/my-maps"),
+            Some(123)
+        );
+        assert_eq!(collection_id("https://osucollector.com/"), None);
+    }
+
+    #[tokio::test]
+    async fn test_collector_collection() {
+        let base_url = test_server::serve(vec![
+            ("/api/collections/7", r#"{"id": 7, "name": "Farm"}"#),
+            (
+                "/api/collections/7/beatmapsv2?perPage=100",
+                r#"{"hasMore": true, "nextPageCursor": 50, "beatmaps": [
+                    {"id": 10, "checksum": "a", "beatmapset_id": 1, "beatmapset": {"id": 1, "title": "one", "artist": "x"}},
+                    {"id": 11, "checksum": "b", "beatmapset_id": 1}
+                ]}"#,
+            ),
+            (
+                "/api/collections/7/beatmapsv2?perPage=100&cursor=50",
+                r#"{"hasMore": true, "nextPageCursor": "2024-01-01 20&x", "beatmaps": [
+                    {"id": 20, "checksum": "c", "beatmapset_id": 2}
+                ]}"#,
+            ),
+            (
+                "/api/collections/7/beatmapsv2?perPage=100&cursor=2024-01-01%2020%26x",
+                r#"{"hasMore": false, "nextPageCursor": null, "beatmaps": []}"#,
+            ),
+        ])
+        .await;
+        let client = CollectorClient::new(Some(base_url), Default::default());
+        let collection = client
+            .collection("https://osucollector.com/collections/7/farm")
+            .await
+            .unwrap();
+        assert_eq!(collection.name, "Farm");
+        assert_eq!(collection.checksums, vec!["a", "b", "c"]);
+        assert_eq!(collection.beatmapsets, vec![1, 2]);
+        let songs = collection
+            .songs
+            .iter()
+            .map(|s| (s.id, s.title.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(songs, vec![(1, "one")]);
+    }
+}
@@ -0,0 +1,159 @@
+use super::{get, API_URL};
+use crate::auth::OsuAuth;
+use crate::client::Beatmapset;
+use crate::ratelimit::RateLimiter;
+use eyre::Result;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackType {
+    Standard,
+    Featured,
+    Tournament,
+    Loved,
+    Chart,
+    Theme,
+    Artist,
+}
+
+impl PackType {
+    pub const ALL: [PackType; 7] = [
+        PackType::Standard,
+        PackType::Featured,
+        PackType::Tournament,
+        PackType::Loved,
+        PackType::Chart,
+        PackType::Theme,
+        PackType::Artist,
+    ];
+
+    fn query(&self) -> &'static str {
+        match self {
+            PackType::Standard => "standard",
+            PackType::Featured => "featured",
+            PackType::Tournament => "tournament",
+            PackType::Loved => "loved",
+            PackType::Chart => "chart",
+            PackType::Theme => "theme",
+            PackType::Artist => "artist",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PackType::Standard => "Standard",
+            PackType::Featured => "Featured Artist",
+            PackType::Tournament => "Tournament",
+            PackType::Loved => "Project Loved",
+            PackType::Chart => "Spotlights",
+            PackType::Theme => "Theme",
+            PackType::Artist => "Artist/Album",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pack {
+    // Identifies the pack, like "S1234"
+    pub tag: String,
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PackPage {
+    #[serde(rename = "beatmap_packs")]
+    pub packs: Vec<Pack>,
+    #[serde(default)]
+    pub cursor_string: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PackDetails {
+    #[serde(default)]
+    beatmapsets: Vec<Beatmapset>,
+}
+
+// The osu! API, or a configured source serving the same routes without authentication
+pub struct PackSource {
+    base_url: String,
+    limiter: Arc<RateLimiter>,
+    auth: Option<Arc<OsuAuth>>,
+}
+
+impl PackSource {
+    pub fn new(base_url: Option<String>, limiter: Arc<RateLimiter>, auth: Arc<OsuAuth>) -> Self {
+        match base_url {
+            Some(base_url) => Self {
+                base_url: base_url.trim_end_matches('/').to_owned(),
+                limiter,
+                auth: None,
+            },
+            None => Self {
+                base_url: API_URL.to_owned(),
+                limiter,
+                auth: Some(auth),
+            },
+        }
+    }
+
+    // One page of packs, newest first
+    pub async fn list(&self, kind: PackType, cursor: Option<String>) -> Result<PackPage> {
+        let mut url = format!("{}/beatmaps/packs?type={}", self.base_url, kind.query());
+        if let Some(cursor) = cursor {
+            url += &format!("&cursor_string={}", urlencoding::encode(&cursor));
+        }
+        get(&self.limiter, self.auth.as_deref(), &url).await
+    }
+
+    pub async fn beatmapsets(&self, tag: &str) -> Result<Vec<Beatmapset>> {
+        let url = format!(
+            "{}/beatmaps/packs/{}",
+            self.base_url,
+            urlencoding::encode(tag)
+        );
+        let pack = get::<PackDetails>(&self.limiter, self.auth.as_deref(), &url).await?;
+        Ok(pack.beatmapsets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    async fn test_pack_source() {
+        let base_url = test_server::serve(vec![
+            (
+                "/beatmaps/packs?type=standard",
+                r#"{"beatmap_packs": [{"tag": "S2", "name": "Pack #2", "author": "a"}], "cursor_string": "abc"}"#,
+            ),
+            (
+                "/beatmaps/packs?type=standard&cursor_string=abc",
+                r#"{"beatmap_packs": [{"tag": "S1", "name": "Pack #1"}], "cursor_string": null}"#,
+            ),
+            (
+                "/beatmaps/packs/S1",
+                r#"{"tag": "S1", "beatmapsets": [{"id": 1, "title": "one"}, {"id": 2, "title": "two"}]}"#,
+            ),
+        ])
+        .await;
+        let auth = OsuAuth::new(0, String::new(), "unused".into(), Default::default());
+        let source = PackSource::new(Some(base_url), Default::default(), Arc::new(auth));
+
+        let page = source.list(PackType::Standard, None).await.unwrap();
+        assert_eq!(page.packs[0].tag, "S2");
+        let page = source
+            .list(PackType::Standard, page.cursor_string)
+            .await
+            .unwrap();
+        assert_eq!(page.packs[0].name, "Pack #1");
+        assert_eq!(page.cursor_string, None);
+
+        let songs = source.beatmapsets("S1").await.unwrap();
+        assert_eq!(songs.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 2]);
+    }
+}
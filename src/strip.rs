@@ -0,0 +1,202 @@
+use eyre::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use zip::{ZipArchive, ZipWriter};
+
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "avi", "flv", "mkv", "webm", "wmv", "m4v", "mpg", "mpeg", "mov",
+];
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "ogg", "wav"];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StripOptions {
+    pub video: bool,
+    // .osb files and the images they and the difficulties' storyboards use
+    pub storyboard: bool,
+}
+
+impl StripOptions {
+    pub fn any(&self) -> bool {
+        self.video || self.storyboard
+    }
+}
+
+// Archive names use `/` and osu! ignores case, storyboards often use `\`
+fn normalize(name: &str) -> String {
+    name.trim()
+        .trim_matches('"')
+        .replace('\\', "/")
+        .to_lowercase()
+}
+
+fn has_extension(name: &str, extensions: &[&str]) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+// Files used by a storyboard, and files a difficulty needs to play
+#[derive(Default)]
+struct References {
+    storyboard: HashSet<String>,
+    keep: HashSet<String>,
+}
+
+impl References {
+    fn scan(&mut self, contents: &str) {
+        for line in contents.lines() {
+            if let Some(audio) = line.strip_prefix("AudioFilename:") {
+                self.keep.insert(normalize(audio));
+                continue;
+            }
+            let fields = line.split(',').collect::<Vec<_>>();
+            match fields.as_slice() {
+                // Background image
+                ["0", _, file, ..] => {
+                    self.keep.insert(normalize(file));
+                }
+                ["Sprite", _, _, file, ..] => {
+                    self.storyboard.insert(normalize(file));
+                }
+                // Frames are numbered before the extension, "a.png" gives "a0.png", "a1.png"…
+                ["Animation", _, _, file, _, _, frames, ..] => {
+                    let file = normalize(file);
+                    let frames = frames.trim().parse::<u32>().unwrap_or(0);
+                    let (stem, ext) = file.rsplit_once('.').unwrap_or((&file, ""));
+                    for frame in 0..frames {
+                        self.storyboard.insert(format!("{}{}.{}", stem, frame, ext));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// Rewrite the .osz at `path` without the assets `options` strip, returning the bytes saved.
+// Difficulties and audio are always kept.
+pub fn strip_osz(path: &Path, options: StripOptions) -> Result<u64> {
+    let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+    let mut references = References::default();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let name = file.name()?.to_ascii_lowercase();
+        if name.ends_with(".osu") || name.ends_with(".osb") {
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            references.scan(&String::from_utf8_lossy(&contents));
+        }
+    }
+
+    let mut removed = Vec::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let name = normalize(&file.name()?);
+        if name.ends_with(".osu")
+            || has_extension(&name, AUDIO_EXTENSIONS)
+            || references.keep.contains(&name)
+        {
+            continue;
+        }
+        let storyboard = name.ends_with(".osb") || references.storyboard.contains(&name);
+        if (options.video && has_extension(&name, VIDEO_EXTENSIONS))
+            || (options.storyboard && storyboard)
+        {
+            removed.push(i);
+        }
+    }
+    if removed.is_empty() {
+        return Ok(0);
+    }
+
+    let size = fs::metadata(path)?.len();
+    let tmp_path = path.with_extension("osz.strip");
+    let result = (|| -> Result<()> {
+        let mut writer = ZipWriter::new(fs::File::create(&tmp_path)?);
+        for i in 0..archive.len() {
+            if !removed.contains(&i) {
+                // Copied as is, without recompressing
+                writer.raw_copy_file(archive.by_index_raw(i)?)?;
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    drop(archive);
+    fs::rename(&tmp_path, path)?;
+    Ok(size.saturating_sub(fs::metadata(path)?.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn names(path: &Path) -> Vec<String> {
+        let archive = ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+        let mut names = archive
+            .file_names()
+            .map(|n| n.unwrap().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_strip_osz() {
+        let path = std::env::temp_dir().join("downloader_test_strip.osz");
+        let osu = "[General]\nAudioFilename: audio.mp3\n[Events]\n0,0,\"bg.jpg\",0,0\nVideo,0,\"video.mp4\"\n";
+        let osb = "[Events]\nSprite,Foreground,Centre,\"sb\\star.png\",320,240\nAnimation,Foreground,Centre,\"sb/fly.png\",320,240,2,100\nSprite,Background,Centre,\"bg.jpg\",320,240\n";
+        let write = |files: &[(&str, &str)]| {
+            let mut writer = ZipWriter::new(fs::File::create(&path).unwrap());
+            for (name, contents) in files {
+                writer
+                    .start_file(*name, SimpleFileOptions::default())
+                    .unwrap();
+                writer.write_all(contents.as_bytes()).unwrap();
+            }
+            writer.finish().unwrap();
+        };
+        let files = [
+            ("a.osu", osu),
+            ("set.osb", osb),
+            ("audio.mp3", "audio"),
+            ("bg.jpg", "bg"),
+            ("video.mp4", "a long video"),
+            ("sb/star.png", "star"),
+            ("sb/fly0.png", "fly"),
+            ("sb/fly1.png", "fly"),
+            ("normal-hitclap.wav", "clap"),
+        ];
+
+        write(&files);
+        let options = StripOptions {
+            video: true,
+            storyboard: false,
+        };
+        assert!(strip_osz(&path, options).unwrap() > 0);
+        assert!(!names(&path).contains(&"video.mp4".to_owned()));
+        assert_eq!(names(&path).len(), files.len() - 1);
+        assert_eq!(strip_osz(&path, options).unwrap(), 0);
+
+        write(&files);
+        let options = StripOptions {
+            video: true,
+            storyboard: true,
+        };
+        strip_osz(&path, options).unwrap();
+        assert_eq!(
+            names(&path),
+            vec!["a.osu", "audio.mp3", "bg.jpg", "normal-hitclap.wav"]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
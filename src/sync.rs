@@ -0,0 +1,113 @@
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+pub const SYNC_STATE_PATH: &str = "sync_state.yaml";
+
+// Progress kept between runs
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct SyncState {
+    // When a search last covered everything ranked up to then
+    #[serde(default)]
+    pub last_sync: Option<DateTime<Utc>>,
+    // Incremental sync progress by search client
+    #[serde(default)]
+    pub checkpoints: HashMap<String, Checkpoint>,
+    // Full catalogue walks by search client
+    #[serde(default)]
+    pub backfills: HashMap<String, Backfill>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct Checkpoint {
+    // Newest set covered by the last complete sync
+    #[serde(default)]
+    pub id: Option<u32>,
+    #[serde(default)]
+    pub ranked_date: Option<DateTime<Utc>>,
+    // Sync that stopped part way, picked up by the next run
+    #[serde(default)]
+    pub pending: Option<PendingSync>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct Backfill {
+    #[serde(default)]
+    pub pending: Option<PendingSync>,
+    // Sets missing locally found so far
+    #[serde(default)]
+    pub found: BTreeSet<u32>,
+    // Sets in the catalogue seen so far
+    #[serde(default)]
+    pub seen: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct PendingSync {
+    pub page: u32,
+    // Page number, or `cursor_string` for the osu! website
+    pub cursor: Option<String>,
+    pub newest_id: Option<u32>,
+    pub newest_ranked_date: Option<DateTime<Utc>>,
+    // Sets missing locally found on the pages before, queued again when the sync resumes
+    #[serde(default)]
+    pub found: BTreeSet<u32>,
+}
+
+impl SyncState {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = std::fs::File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_yaml::to_writer(file, self)?;
+        Ok(())
+    }
+}
+
+// Found sets that still aren't in the library
+pub fn missing(found: &BTreeSet<u32>, local_songs: &HashSet<u32>) -> Vec<u32> {
+    found
+        .iter()
+        .copied()
+        .filter(|id| !local_songs.contains(id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backfill_resume() {
+        let path =
+            std::env::temp_dir().join(format!("sync_state_test_{}.yaml", std::process::id()));
+        let mut state = SyncState::default();
+        let backfill = state.backfills.entry("nerinyan".to_owned()).or_default();
+        backfill.found.extend([5, 3, 1]);
+        backfill.seen = 100;
+        backfill.pending = Some(PendingSync {
+            page: 2,
+            cursor: Some("2".to_owned()),
+            ..Default::default()
+        });
+        state.save(&path).unwrap();
+        let loaded = SyncState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let backfill = &loaded.backfills["nerinyan"];
+        let pending = backfill.pending.as_ref().unwrap();
+        assert_eq!((pending.page, pending.cursor.as_deref()), (2, Some("2")));
+        assert_eq!(backfill.seen, 100);
+        // Sets downloaded in the meantime drop out of the final diff
+        let local_songs = HashSet::from([3]);
+        assert_eq!(missing(&backfill.found, &local_songs), vec![1, 5]);
+    }
+}
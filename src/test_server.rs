@@ -0,0 +1,38 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// Serves canned JSON by path and query, standing in for the API in tests
+pub async fn serve(routes: Vec<(&'static str, &'static str)>) -> String {
+    let routes = routes
+        .into_iter()
+        .map(|(path, body)| {
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            (path, response.into_bytes())
+        })
+        .collect();
+    serve_raw(routes).await
+}
+
+// Answers each path with its raw response and hangs up, anything else is a 404
+pub async fn serve_raw(routes: Vec<(&'static str, Vec<u8>)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = vec![0; 4096];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let response = match routes.iter().find(|(route, _)| *route == path) {
+                Some((_, response)) => response.as_slice(),
+                None => b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            };
+            let _ = stream.write_all(response).await;
+        }
+    });
+    base_url
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::de::IntoDeserializer;
 use serde::Deserialize;
 
@@ -7,9 +8,44 @@ where
     T: serde::Deserialize<'de>,
 {
     let opt = Option::<String>::deserialize(de)?;
-    let opt = opt.as_ref().map(String::as_str);
+    let opt = opt.as_deref();
     match opt {
         None | Some("") => Ok(None),
         Some(s) => T::deserialize(s.into_deserializer()).map(Some),
     }
 }
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrOther {
+    String(String),
+    Other(serde::de::IgnoredAny),
+}
+
+// Mirrors disagree on date formats, anything unrecognised is treated as missing
+pub fn lenient_datetime<'de, D>(de: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = match Option::<StringOrOther>::deserialize(de)? {
+        Some(StringOrOther::String(s)) => s,
+        _ => return Ok(None),
+    };
+    if let Ok(date) = DateTime::parse_from_rfc3339(&s) {
+        return Ok(Some(date.with_timezone(&Utc)));
+    }
+    Ok(NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|date| date.and_utc()))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}